@@ -0,0 +1 @@
+CREATE TABLE IF NOT EXISTS wikis (id INTEGER PRIMARY KEY, user TEXT, content TEXT, password TEXT);
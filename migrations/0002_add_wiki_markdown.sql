@@ -0,0 +1,3 @@
+-- Raw Markdown is the source of truth; `content` keeps the rendered HTML as a cache.
+-- Rows created before this migration have a NULL `markdown` until backfilled.
+ALTER TABLE wikis ADD COLUMN markdown TEXT;
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS revisions (
+    id INTEGER PRIMARY KEY,
+    user TEXT NOT NULL,
+    markdown TEXT NOT NULL,
+    summary TEXT,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS revisions_user_idx ON revisions (user, id);
+
+-- Seed the history with the current head of every wiki that already has a Markdown source.
+INSERT INTO revisions (user, markdown, summary, created_at)
+SELECT user, markdown, 'Imported existing version', CAST(strftime('%s', 'now') AS INTEGER)
+FROM wikis WHERE markdown IS NOT NULL;
@@ -0,0 +1,13 @@
+-- Pages other than the home page of a wiki, which stays in `wikis`.
+CREATE TABLE IF NOT EXISTS pages (
+    id INTEGER PRIMARY KEY,
+    user TEXT NOT NULL,
+    path TEXT NOT NULL,
+    markdown TEXT NOT NULL,
+    content TEXT NOT NULL,
+    updated_at INTEGER NOT NULL,
+    UNIQUE (user, path)
+);
+
+-- Revisions of the home page keep an empty page path.
+ALTER TABLE revisions ADD COLUMN page TEXT NOT NULL DEFAULT '';
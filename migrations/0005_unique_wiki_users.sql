@@ -0,0 +1,3 @@
+-- Usernames are unique. Deployments holding duplicate rows must resolve them
+-- first with `personal-wiki duplicates --resolve`.
+CREATE UNIQUE INDEX IF NOT EXISTS wikis_user_unique ON wikis (user);
@@ -0,0 +1,30 @@
+-- Full-text index over the Markdown of every home page (page = '') and page,
+-- kept up to date by triggers so that every write path maintains it.
+CREATE VIRTUAL TABLE IF NOT EXISTS search_index USING fts5(user UNINDEXED, page UNINDEXED, body);
+
+INSERT INTO search_index (user, page, body)
+SELECT user, '', COALESCE(markdown, content) FROM wikis;
+INSERT INTO search_index (user, page, body)
+SELECT user, path, markdown FROM pages;
+
+CREATE TRIGGER IF NOT EXISTS wikis_search_insert AFTER INSERT ON wikis BEGIN
+    INSERT INTO search_index (user, page, body) VALUES (new.user, '', COALESCE(new.markdown, new.content));
+END;
+CREATE TRIGGER IF NOT EXISTS wikis_search_update AFTER UPDATE OF content, markdown ON wikis BEGIN
+    DELETE FROM search_index WHERE user = old.user AND page = '';
+    INSERT INTO search_index (user, page, body) VALUES (new.user, '', COALESCE(new.markdown, new.content));
+END;
+CREATE TRIGGER IF NOT EXISTS wikis_search_delete AFTER DELETE ON wikis BEGIN
+    DELETE FROM search_index WHERE user = old.user AND page = '';
+END;
+
+CREATE TRIGGER IF NOT EXISTS pages_search_insert AFTER INSERT ON pages BEGIN
+    INSERT INTO search_index (user, page, body) VALUES (new.user, new.path, new.markdown);
+END;
+CREATE TRIGGER IF NOT EXISTS pages_search_update AFTER UPDATE OF markdown ON pages BEGIN
+    DELETE FROM search_index WHERE user = old.user AND page = old.path;
+    INSERT INTO search_index (user, page, body) VALUES (new.user, new.path, new.markdown);
+END;
+CREATE TRIGGER IF NOT EXISTS pages_search_delete AFTER DELETE ON pages BEGIN
+    DELETE FROM search_index WHERE user = old.user AND page = old.path;
+END;
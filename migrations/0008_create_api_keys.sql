@@ -0,0 +1,12 @@
+-- Named API keys for scripts and CI. Only the SHA-256 digest of each key is
+-- stored, scopes are space separated.
+CREATE TABLE IF NOT EXISTS api_keys (
+    id INTEGER PRIMARY KEY,
+    user TEXT NOT NULL,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    last_used_at INTEGER,
+    UNIQUE (user, name)
+);
@@ -0,0 +1,8 @@
+-- Security relevant account events, such as password changes.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY,
+    user TEXT NOT NULL,
+    event TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS audit_log_user_idx ON audit_log (user, id);
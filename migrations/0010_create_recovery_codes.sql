@@ -0,0 +1,8 @@
+-- One-time recovery codes. Only the SHA-256 digest of each code is stored and
+-- codes are deleted once redeemed.
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id INTEGER PRIMARY KEY,
+    user TEXT NOT NULL,
+    code_hash TEXT NOT NULL,
+    UNIQUE (user, code_hash)
+);
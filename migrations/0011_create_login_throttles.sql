@@ -0,0 +1,6 @@
+-- Failed password or recovery code attempts per account, for lockouts.
+CREATE TABLE IF NOT EXISTS login_throttles (
+    user TEXT PRIMARY KEY,
+    failures INTEGER NOT NULL,
+    locked_until INTEGER
+);
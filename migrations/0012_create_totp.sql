@@ -0,0 +1,9 @@
+-- Time-based one-time password (RFC 6238) settings. A secret is pending until
+-- confirmed with a first code. `last_step` is the time step of the last code
+-- accepted, so that a code cannot be replayed.
+CREATE TABLE IF NOT EXISTS totp (
+    user TEXT PRIMARY KEY,
+    secret TEXT NOT NULL,
+    enabled INTEGER NOT NULL DEFAULT 0,
+    last_step INTEGER
+);
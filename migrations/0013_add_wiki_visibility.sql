@@ -0,0 +1,3 @@
+-- Who can read a wiki: 'public' (anyone, listed in search), 'unlisted' (anyone
+-- with the link, never listed) or 'private' (the owner and share links only).
+ALTER TABLE wikis ADD COLUMN visibility TEXT NOT NULL DEFAULT 'public';
@@ -0,0 +1,10 @@
+-- Revocable links granting read access to a private wiki. Only the SHA-256
+-- digest of each token is stored.
+CREATE TABLE IF NOT EXISTS share_links (
+    id INTEGER PRIMARY KEY,
+    user TEXT NOT NULL,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at INTEGER NOT NULL,
+    UNIQUE (user, name)
+);
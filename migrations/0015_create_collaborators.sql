@@ -0,0 +1,11 @@
+-- Accounts other than the owner allowed to edit a wiki. `user` is the account
+-- of the collaborator, `wiki` the username of the wiki they may edit, `role`
+-- either 'editor' or 'admin'.
+CREATE TABLE IF NOT EXISTS collaborators (
+    wiki TEXT NOT NULL,
+    user TEXT NOT NULL,
+    role TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (wiki, user)
+);
+CREATE INDEX IF NOT EXISTS collaborators_user_idx ON collaborators (user);
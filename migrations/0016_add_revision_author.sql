@@ -0,0 +1,3 @@
+-- The account that saved a revision. NULL for revisions saved before
+-- collaborators existed, which were all made by the owner.
+ALTER TABLE revisions ADD COLUMN author TEXT;
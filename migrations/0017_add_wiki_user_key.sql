@@ -0,0 +1,6 @@
+-- Usernames that differ only by case or by confusable characters (`alice`,
+-- `Alice`, `al1ce`) share the same `user_key`, see `usernames::username_key`.
+-- Rows created before this migration have a NULL key until backfilled when the
+-- store is opened.
+ALTER TABLE wikis ADD COLUMN user_key TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS wikis_user_key_unique ON wikis (user_key);
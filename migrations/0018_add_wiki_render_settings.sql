@@ -0,0 +1,3 @@
+-- Markdown constructs enabled on top of CommonMark when rendering a wiki, as a
+-- comma separated list such as 'gfm,math'. See `store::RenderSettings`.
+ALTER TABLE wikis ADD COLUMN render_settings TEXT NOT NULL DEFAULT 'gfm';
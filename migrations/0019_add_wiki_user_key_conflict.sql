@@ -0,0 +1,4 @@
+-- Set on legacy rows whose username turned out to be confusable with an older
+-- one when backfilling `user_key`, so that they are not looked at again on
+-- every start.
+ALTER TABLE wikis ADD COLUMN user_key_conflict INTEGER NOT NULL DEFAULT 0;
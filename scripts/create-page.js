@@ -0,0 +1,29 @@
+document.getElementById('createPage').addEventListener('click', async () => {
+    const btn = document.getElementById('createPage');
+    const status = document.getElementById('createStatus');
+    const content = document.getElementById('pageContent').value;
+    const summary = document.getElementById('pageSummary').value;
+    if (!content) {
+        status.textContent = "Please write the content of the page";
+        return;
+    }
+    btn.textContent = "Creating page...";
+    btn.classList.add("disabled");
+    // the session cookie set by "Log In" authenticates the request
+    const response = await fetch("/wikis/pages", {
+            method: "POST",
+            body: JSON.stringify({ "username": btn.dataset.username, "path": btn.dataset.path, "content": content, "summary": summary || null }),
+            headers: {"Content-Type": "application/json"},
+        }
+    )
+    if (response.ok) {
+        const jsonResponse = await response.json()
+        if (jsonResponse.success) {
+            window.location.href = jsonResponse.url;
+            return;
+        }
+        status.textContent = `An error occurred: ${jsonResponse.error}`;
+    }
+    btn.textContent = "Create Page";
+    btn.classList.remove("disabled");
+});
@@ -37,6 +37,11 @@ document.getElementById('createWiki').addEventListener('click', async () => {
                     btn.classList.remove("disabled");
                     document.getElementById('wikiLink').value = `https://personalwiki.com.de/wikis/${username}`;
                     document.getElementById('linkContainer').classList.remove('hidden');
+                    // store these somewhere safe: they are the only way back in without the password
+                    if (jsonResponse.recovery_codes) {
+                        document.getElementById('recoveryCodes').value = jsonResponse.recovery_codes.join("\n");
+                        document.getElementById('recoveryContainer').classList.remove('hidden');
+                    }
                 } else {
                     btn.textContent = "Create Wiki";
                     btn.classList.remove("disabled");
@@ -62,10 +67,12 @@ document.getElementById('updateWiki').addEventListener('click', async () => {
     const username = document.getElementById('username').value;
     const password = document.getElementById('password').value;
     const wikiText = document.getElementById('wiki').value;
-    if (username && wikiText && password) {
+    const summary = document.getElementById('summary').value;
+    // without a password the session cookie set by "Log In" is used
+    if (username && wikiText) {
         const response = await fetch("/wikis", {
                 method: "PATCH",
-                body: JSON.stringify({ "username": username, "content": wikiText, "password": password }),
+                body: JSON.stringify({ "username": username, "content": wikiText, "password": password || null, "summary": summary || null }),
                 headers: {"Content-Type": "application/json"},
             }
         )
@@ -93,7 +100,7 @@ document.getElementById('updateWiki').addEventListener('click', async () => {
     }  else {
         btn.textContent = "Update Wiki";
         btn.classList.remove("disabled");
-        document.getElementById('wikiLink').value = `Please make sure to have filled out the username, the password and the wiki text fields`;
+        document.getElementById('wikiLink').value = `Please make sure to have filled out the username and the wiki text fields`;
         document.getElementById('linkContainer').classList.remove('hidden');
         document.getElementById('copyButton').classList.add('hidden');
     }
@@ -105,10 +112,10 @@ document.getElementById('deleteWiki').addEventListener('click', async () => {
     btn.classList.add("disabled");
     const username = document.getElementById('username').value;
     const password = document.getElementById('password').value;
-    if (username && password) {
+    if (username) {
         const response = await fetch("/wikis", {
                 method: "DELETE",
-                body: JSON.stringify({ "username": username, "password": password }),
+                body: JSON.stringify({ "username": username, "password": password || null }),
                 headers: {"Content-Type": "application/json"},
             }
         )
@@ -134,8 +141,107 @@ document.getElementById('deleteWiki').addEventListener('click', async () => {
     }  else {
         btn.textContent = "Delete Wiki";
         btn.classList.remove("disabled");
-        document.getElementById('wikiLink').value = `Please make sure to have filled out the username, the password and the wiki text fields`;
+        document.getElementById('wikiLink').value = `Please make sure to have filled out the username field`;
+        document.getElementById('linkContainer').classList.remove('hidden');
+        document.getElementById('copyButton').classList.add('hidden');
+    }
+});
+
+document.getElementById('loadWiki').addEventListener('click', async () => {
+    const btn = document.getElementById('loadWiki');
+    btn.textContent = "Loading wiki...";
+    btn.classList.add("disabled");
+    const username = document.getElementById('username').value;
+    const password = document.getElementById('password').value;
+    if (username) {
+        const response = await fetch("/wikis/source", {
+                method: "POST",
+                body: JSON.stringify({ "username": username, "password": password || null }),
+                headers: {"Content-Type": "application/json"},
+            }
+        )
+        if (response.ok) {
+            const jsonResponse = await response.json()
+            // validate
+            if ("success" in jsonResponse && "error" in jsonResponse && "content" in jsonResponse) {
+                if (jsonResponse.success) {
+                    btn.textContent = "Loaded Wiki!";
+                    setTimeout(() => {
+                        btn.textContent = "Load Wiki";
+                    }, 2000);
+                    btn.classList.remove("disabled");
+                    document.getElementById('wiki').value = jsonResponse.content;
+                } else {
+                    btn.textContent = "Load Wiki";
+                    btn.classList.remove("disabled");
+                    document.getElementById('wikiLink').value = `An error occurred: ${jsonResponse.error}`;
+                    document.getElementById('linkContainer').classList.remove('hidden');
+                    document.getElementById('copyButton').classList.add('hidden');
+                }
+            }
+        }
+    }  else {
+        btn.textContent = "Load Wiki";
+        btn.classList.remove("disabled");
+        document.getElementById('wikiLink').value = `Please make sure to have filled out the username field`;
         document.getElementById('linkContainer').classList.remove('hidden');
         document.getElementById('copyButton').classList.add('hidden');
     }
-});
\ No newline at end of file
+});
+
+document.getElementById('logIn').addEventListener('click', async () => {
+    const btn = document.getElementById('logIn');
+    btn.textContent = "Logging in...";
+    btn.classList.add("disabled");
+    const username = document.getElementById('username').value;
+    const password = document.getElementById('password').value;
+    const code = document.getElementById('code').value;
+    if (username && password) {
+        // the session is kept in an HttpOnly cookie, the password field can be left empty afterwards
+        const response = await fetch("/login", {
+                method: "POST",
+                body: JSON.stringify({ "username": username, "password": password, "code": code || null }),
+                headers: {"Content-Type": "application/json"},
+            }
+        )
+        if (response.ok) {
+            const jsonResponse = await response.json()
+            // validate
+            if ("success" in jsonResponse && "error" in jsonResponse) {
+                if (jsonResponse.success) {
+                    btn.textContent = "Logged In!";
+                    setTimeout(() => {
+                        btn.textContent = "Log In";
+                    }, 2000);
+                    btn.classList.remove("disabled");
+                    document.getElementById('password').value = "";
+                    document.getElementById('code').value = "";
+                } else {
+                    btn.textContent = "Log In";
+                    btn.classList.remove("disabled");
+                    document.getElementById('wikiLink').value = `An error occurred: ${jsonResponse.error}`;
+                    document.getElementById('linkContainer').classList.remove('hidden');
+                    document.getElementById('copyButton').classList.add('hidden');
+                }
+            }
+        }
+    }  else {
+        btn.textContent = "Log In";
+        btn.classList.remove("disabled");
+        document.getElementById('wikiLink').value = `Please make sure to have filled out the username and the password fields`;
+        document.getElementById('linkContainer').classList.remove('hidden');
+        document.getElementById('copyButton').classList.add('hidden');
+    }
+});
+
+document.getElementById('logOut').addEventListener('click', async () => {
+    const btn = document.getElementById('logOut');
+    btn.textContent = "Logging out...";
+    btn.classList.add("disabled");
+    await fetch("/logout", { method: "POST" });
+    btn.textContent = "Logged Out!";
+    setTimeout(() => {
+        btn.textContent = "Log Out";
+    }, 2000);
+    btn.classList.remove("disabled");
+});
@@ -0,0 +1,898 @@
+//! Authentication of wiki owners: password checks, login sessions and API keys.
+//!
+//! Write endpoints accept either the account password in their JSON body or a
+//! token: a session issued by `/login` or an API key, sent as an
+//! `Authorization: Bearer` header (API clients), or a session sent as the
+//! `session` cookie (the browser).
+//!
+//! Owners who enabled two-factor authentication (see [`crate::totp`]) also
+//! need a code wherever they use their password.
+
+use crate::hashing::{hash_password, needs_rehash, verify_password};
+use crate::keys::API_KEY_PREFIX;
+use crate::store::{unix_now, Role, StoreError, Wiki, WikiStore};
+use crate::totp::accept_code;
+use crate::{AppState, DeleteWikiResponse};
+use axum::{
+    extract::{FromRequestParts, State},
+    http::{
+        header::{AUTHORIZATION, COOKIE, SET_COOKIE},
+        request::Parts,
+    },
+    response::{AppendHeaders, IntoResponse, Json, Response},
+};
+use derivative::Derivative;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::fmt;
+use tracing::{error, info, instrument, warn};
+
+pub const SESSION_COOKIE: &str = "session";
+/// Consecutive failed verifications after which an account gets locked.
+pub const LOCKOUT_THRESHOLD: u32 = 5;
+const BASE_LOCKOUT_SECS: i64 = 30;
+const MAX_LOCKOUT_SECS: i64 = 60 * 60;
+/// Audit log event recorded when the owner changes their password.
+pub const PASSWORD_CHANGED: &str = "password_changed";
+/// Sessions expire after a week.
+pub const SESSION_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// What an API key may be used for. Passwords and sessions grant every scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    /// Read the Markdown source of the wiki.
+    ReadSource,
+    /// Create, update and restore pages.
+    Write,
+    /// Delete pages or the whole wiki.
+    Delete,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ReadSource => "read-source",
+            Self::Write => "write",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a client proved its identity with.
+pub enum Credential {
+    Password(String),
+    Session(String),
+    ApiKey(String),
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Password(_) => write!(f, "Password(..)"),
+            Self::Session(_) => write!(f, "Session(..)"),
+            Self::ApiKey(_) => write!(f, "ApiKey(..)"),
+        }
+    }
+}
+
+impl Credential {
+    /// A password in the request body takes precedence over a token. API keys
+    /// are told apart from session tokens by their prefix.
+    pub fn resolve(password: Option<&str>, token: &AuthToken) -> Option<Self> {
+        match (password, &token.0) {
+            (Some(p), _) if !p.is_empty() => Some(Self::Password(p.to_string())),
+            (_, Some(t)) if t.starts_with(API_KEY_PREFIX) => Some(Self::ApiKey(t.clone())),
+            (_, Some(t)) => Some(Self::Session(t.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// The session token or API key sent with a request, if any.
+pub struct AuthToken(pub Option<String>);
+
+impl fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "AuthToken(..)"),
+            None => write!(f, "AuthToken(None)"),
+        }
+    }
+}
+
+fn bearer_token(parts: &Parts) -> Option<String> {
+    let value = parts.headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ")?.trim();
+    (!token.is_empty()).then(|| token.to_string())
+}
+
+fn cookie_token(parts: &Parts) -> Option<String> {
+    parts
+        .headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, value)| *name == SESSION_COOKIE && !value.is_empty())
+        .map(|(_, value)| value.to_string())
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AuthToken {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(bearer_token(parts).or_else(|| cookie_token(parts))))
+    }
+}
+
+/// Generates a random token with 256 bits of entropy, hex encoded.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::fill(&mut bytes);
+    to_hex(&bytes)
+}
+
+/// Tokens and API keys are only stored as their SHA-256 digest, so a leaked
+/// database does not hand out valid credentials.
+pub fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// How a password is checked against two-factor authentication, if the owner
+/// enabled it. Sessions and API keys can only be obtained with a code, so they
+/// are never asked for one.
+enum SecondFactor<'a> {
+    /// The request carries this code, if any.
+    Code(Option<&'a str>),
+    /// The request has no room for a code, so the password alone is refused.
+    Unavailable,
+    /// The caller checks a second factor itself.
+    Skip,
+}
+
+/// Loads the wiki of `username` and checks that `credential` grants `scope`
+/// on it.
+pub async fn authenticate(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    scope: Scope,
+) -> Result<Wiki, String> {
+    verify(
+        store,
+        username,
+        credential,
+        Some(scope),
+        SecondFactor::Unavailable,
+    )
+    .await
+}
+
+/// Like [`authenticate`], for account management that API keys must not be
+/// able to perform, such as minting more keys.
+pub async fn authenticate_owner(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+) -> Result<Wiki, String> {
+    verify(store, username, credential, None, SecondFactor::Unavailable).await
+}
+
+/// Like [`authenticate_owner`], with a two-factor code to go with a password.
+pub async fn authenticate_with_code(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    code: Option<&str>,
+) -> Result<Wiki, String> {
+    verify(store, username, credential, None, SecondFactor::Code(code)).await
+}
+
+/// Like [`authenticate_owner`], leaving the second factor to the caller.
+pub async fn authenticate_first_factor(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+) -> Result<Wiki, String> {
+    verify(store, username, credential, None, SecondFactor::Skip).await
+}
+
+/// Checks that `credential` grants `scope` on the wiki of `wiki`, either as
+/// its owner or as a collaborator (see [`crate::collaborators`]). Editors may
+/// read the source and write, deleting takes an admin.
+///
+/// Sessions and API keys identify their account. Passwords are checked
+/// against `account`, or the owner when none is given. Returns the account
+/// acting on the wiki, to attribute revisions to.
+pub async fn authorize(
+    store: &dyn WikiStore,
+    wiki: &str,
+    account: Option<&str>,
+    credential: Option<&Credential>,
+    scope: Scope,
+) -> Result<String, String> {
+    let acting = match credential {
+        Some(Credential::Password(_)) | None => account.unwrap_or(wiki).to_string(),
+        Some(Credential::Session(token)) => match store.get_session(&hash_token(token)).await {
+            Ok(Some(session)) => session.username,
+            Ok(None) => return Err("Invalid or expired session, please log in again".to_string()),
+            Err(e) => return Err(e.to_string()),
+        },
+        Some(Credential::ApiKey(key)) => match store.get_api_key(&hash_token(key)).await {
+            Ok(Some(api_key)) => api_key.username,
+            Ok(None) => return Err("Invalid or revoked API key".to_string()),
+            Err(e) => return Err(e.to_string()),
+        },
+    };
+    if acting != wiki {
+        // Checked before the credential so that strangers learn nothing
+        // about the wiki, and do not lock its owner out.
+        let required = match scope {
+            Scope::ReadSource | Scope::Write => Role::Editor,
+            Scope::Delete => Role::Admin,
+        };
+        match store.get_collaborator_role(wiki, &acting).await {
+            Ok(Some(role)) if role >= required => {}
+            Ok(Some(_)) => {
+                return Err(format!(
+                    "This operation requires the {} role on the wiki of user {}",
+                    required.as_str(),
+                    wiki
+                ))
+            }
+            Ok(None) => {
+                return Err(format!(
+                    "You are not a collaborator on the wiki of user {}",
+                    wiki
+                ))
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    authenticate(store, &acting, credential, scope).await?;
+    Ok(acting)
+}
+
+/// `scope` is `None` when only the password or a session is accepted.
+async fn verify(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    scope: Option<Scope>,
+    second_factor: SecondFactor<'_>,
+) -> Result<Wiki, String> {
+    let credential = credential.ok_or_else(|| "Missing password or session token".to_string())?;
+    let wiki = match store.get_wiki(username).await {
+        Ok(Some(w)) => w,
+        Ok(None) => return Err("User does not exist".to_string()),
+        Err(e) => return Err(e.to_string()),
+    };
+    match credential {
+        Credential::Password(password) => {
+            let throttled = check_throttle(store, username).await?;
+            match verify_password(password, &wiki.password).await {
+                Ok(true) => {
+                    check_second_factor(store, username, second_factor).await?;
+                    if throttled {
+                        reset_throttle(store, username).await?;
+                    }
+                    if needs_rehash(&wiki.password) {
+                        upgrade_hash(store, username, password, &wiki.password).await;
+                    }
+                    Ok(wiki)
+                }
+                Ok(false) => {
+                    record_failure(store, username).await?;
+                    Err("Wrong username or password".to_string())
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Credential::Session(token) => match store.get_session(&hash_token(token)).await {
+            Ok(Some(session))
+                if session.username == username && session.expires_at > unix_now() =>
+            {
+                Ok(wiki)
+            }
+            Ok(_) => Err("Invalid or expired session, please log in again".to_string()),
+            Err(e) => Err(e.to_string()),
+        },
+        Credential::ApiKey(key) => {
+            let Some(scope) = scope else {
+                return Err("API keys cannot be used for this operation".to_string());
+            };
+            let api_key = match store.get_api_key(&hash_token(key)).await {
+                Ok(Some(k)) if k.username == username => k,
+                Ok(_) => return Err("Invalid or revoked API key".to_string()),
+                Err(e) => return Err(e.to_string()),
+            };
+            if !api_key.scopes.iter().any(|s| s == scope.as_str()) {
+                return Err(format!("This API key lacks the '{}' scope", scope));
+            }
+            store
+                .touch_api_key(api_key.id, unix_now())
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(wiki)
+        }
+    }
+}
+
+/// Passes unless `username` enabled two-factor authentication and no valid
+/// code comes with the password. Wrong codes count as failed verifications.
+async fn check_second_factor(
+    store: &dyn WikiStore,
+    username: &str,
+    second_factor: SecondFactor<'_>,
+) -> Result<(), String> {
+    let totp = match store.get_totp(username).await {
+        Ok(Some(t)) if t.enabled => t,
+        Ok(_) => return Ok(()),
+        Err(e) => return Err(e.to_string()),
+    };
+    match second_factor {
+        SecondFactor::Skip => Ok(()),
+        SecondFactor::Unavailable => Err(
+            "Two-factor authentication is enabled, log in with a code and use the session instead"
+                .to_string(),
+        ),
+        SecondFactor::Code(None) => Err("A two-factor authentication code is required".to_string()),
+        SecondFactor::Code(Some(code)) => {
+            if accept_code(store, username, &totp, code).await? {
+                return Ok(());
+            }
+            record_failure(store, username).await?;
+            Err("Wrong or already used two-factor authentication code".to_string())
+        }
+    }
+}
+
+/// Replaces a legacy (bcrypt) hash by an Argon2id one while the password is
+/// at hand. Failing to do so is not fatal, it is retried on the next login.
+async fn upgrade_hash(store: &dyn WikiStore, username: &str, password: &str, old_hash: &str) {
+    let upgraded = match hash_password(password).await {
+        Ok(new_hash) => store
+            .rehash_password(username, old_hash, &new_hash)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    match upgraded {
+        Ok(()) => {
+            info!(event = "RehashPassword", data_id = %username, "Password hash upgraded to Argon2id")
+        }
+        Err(e) => warn!(event = "RehashPassword", data_id = %username, "{}", e),
+    }
+}
+
+/// How long an account stays locked after `failures` consecutive failed
+/// verifications: not at all below [`LOCKOUT_THRESHOLD`], then doubling from
+/// 30 seconds with every further failure, up to an hour.
+fn lockout_secs(failures: u32) -> Option<i64> {
+    let excess = failures.checked_sub(LOCKOUT_THRESHOLD)?;
+    Some((BASE_LOCKOUT_SECS << excess.min(16)).min(MAX_LOCKOUT_SECS))
+}
+
+/// Fails with a distinct error while `username` is locked out, without
+/// checking any credential. Otherwise returns whether failed attempts are on
+/// record, which a successful verification then has to reset.
+pub async fn check_throttle(store: &dyn WikiStore, username: &str) -> Result<bool, String> {
+    match store.get_login_throttle(username).await {
+        Ok(Some(throttle)) => {
+            let remaining = throttle.locked_until.unwrap_or_default() - unix_now();
+            if remaining > 0 {
+                return Err(format!(
+                    "Too many failed attempts, this account is locked for {} more seconds",
+                    remaining
+                ));
+            }
+            Ok(true)
+        }
+        Ok(None) => Ok(false),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Counts a failed verification of `username`, locking the account once
+/// there are too many.
+pub async fn record_failure(store: &dyn WikiStore, username: &str) -> Result<(), String> {
+    let failures = store
+        .record_login_failure(username)
+        .await
+        .map_err(|e| e.to_string())?;
+    if let Some(secs) = lockout_secs(failures) {
+        store
+            .lock_login(username, unix_now() + secs)
+            .await
+            .map_err(|e| e.to_string())?;
+        warn!(event = "AccountLocked", data_id = %username, "Locked for {} seconds after {} failed attempts", secs, failures);
+    }
+    Ok(())
+}
+
+pub async fn reset_throttle(store: &dyn WikiStore, username: &str) -> Result<(), String> {
+    store
+        .reset_login_failures(username)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Verifies the password (and two-factor `code`, if enabled) once and opens a
+/// session, returning its token and expiry.
+pub async fn login(
+    store: &dyn WikiStore,
+    username: &str,
+    password: &str,
+    code: Option<&str>,
+) -> Result<(String, i64), String> {
+    authenticate_with_code(
+        store,
+        username,
+        Some(&Credential::Password(password.to_string())),
+        code,
+    )
+    .await?;
+    let token = generate_token();
+    let expires_at = unix_now() + SESSION_TTL_SECS;
+    store
+        .create_session(username, &hash_token(&token), expires_at)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok((token, expires_at))
+}
+
+pub async fn logout(store: &dyn WikiStore, token: &str) -> Result<(), String> {
+    match store.delete_session(&hash_token(token)).await {
+        Ok(()) | Err(StoreError::NotFound) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Replaces the password of `username` after checking the current one. Every
+/// session and API key of the account is revoked, since they may have been
+/// obtained with the old password.
+pub async fn change_password(
+    store: &dyn WikiStore,
+    username: &str,
+    current_password: &str,
+    code: Option<&str>,
+    new_password: &str,
+) -> Option<String> {
+    let current = Credential::Password(current_password.to_string());
+    if let Err(e) = authenticate_with_code(store, username, Some(&current), code).await {
+        return Some(e);
+    }
+    if new_password.is_empty() {
+        return Some("The new password cannot be empty".to_string());
+    }
+    let hashed = match hash_password(new_password).await {
+        Ok(h) => h,
+        Err(e) => return Some(e.to_string()),
+    };
+    store
+        .change_password(username, &hashed, PASSWORD_CHANGED)
+        .await
+        .err()
+        .map(|e| e.to_string())
+}
+
+fn session_cookie(token: &str, max_age: i64) -> String {
+    format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; Secure; SameSite=Strict",
+        SESSION_COOKIE, token, max_age
+    )
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct LoginRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    pub password: String,
+    /// Two-factor authentication code, once enabled.
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub code: Option<String>,
+}
+
+#[derive(Serialize, Derivative)]
+#[derivative(Debug)]
+pub struct LoginResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    #[derivative(Debug = "ignore")]
+    pub token: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct ChangePasswordRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    pub current_password: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub code: Option<String>,
+    #[derivative(Debug = "ignore")]
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct AuditLogRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AuditEntry {
+    pub event: String,
+    pub created_at: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AuditLogResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    pub events: Vec<AuditEntry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LogoutResponse {
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[instrument(skip(state))]
+pub async fn login_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<LoginRequest>,
+) -> Response {
+    match login(
+        state.store.as_ref(),
+        &payload.username,
+        &payload.password,
+        payload.code.as_deref(),
+    )
+    .await
+    {
+        Ok((token, expires_at)) => {
+            info!(event = "Login", data_id = %payload.username, "Session successfully created");
+            (
+                AppendHeaders([(SET_COOKIE, session_cookie(&token, SESSION_TTL_SECS))]),
+                Json(LoginResponse {
+                    success: true,
+                    error: None,
+                    token: Some(token),
+                    expires_at: Some(expires_at),
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!(event = "Login", data_id = %payload.username, "{}", e);
+            Json(LoginResponse {
+                success: false,
+                error: Some(e),
+                token: None,
+                expires_at: None,
+            })
+            .into_response()
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn logout_handler(State(state): State<AppState>, token: AuthToken) -> Response {
+    let result = match &token.0 {
+        Some(t) => logout(state.store.as_ref(), t).await,
+        None => Err("Missing session token".to_string()),
+    };
+    let response = match result {
+        Ok(()) => {
+            info!(event = "Logout", "Session successfully revoked");
+            LogoutResponse {
+                success: true,
+                error: None,
+            }
+        }
+        Err(e) => {
+            error!(event = "Logout", "{}", e);
+            LogoutResponse {
+                success: false,
+                error: Some(e),
+            }
+        }
+    };
+    // always clear the cookie, the session may have expired already
+    (
+        AppendHeaders([(SET_COOKIE, session_cookie("", 0))]),
+        Json(response),
+    )
+        .into_response()
+}
+
+#[instrument(skip(state))]
+pub async fn change_password_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Response {
+    match change_password(
+        state.store.as_ref(),
+        &payload.username,
+        &payload.current_password,
+        payload.code.as_deref(),
+        &payload.new_password,
+    )
+    .await
+    {
+        Some(e) => {
+            error!(event = "ChangePassword", data_id = %payload.username, "{}", e);
+            Json(DeleteWikiResponse {
+                success: false,
+                error: Some(e),
+            })
+            .into_response()
+        }
+        None => {
+            info!(event = "ChangePassword", data_id = %payload.username, "Password successfully changed");
+            // the session of this browser was revoked with all the others
+            (
+                AppendHeaders([(SET_COOKIE, session_cookie("", 0))]),
+                Json(DeleteWikiResponse {
+                    success: true,
+                    error: None,
+                }),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn audit_log_handler(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<AuditLogRequest>,
+) -> Json<AuditLogResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    let events = match authenticate_owner(
+        state.store.as_ref(),
+        &payload.username,
+        credential.as_ref(),
+    )
+    .await
+    {
+        Ok(_) => state
+            .store
+            .list_audit_events(&payload.username)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    match events {
+        Ok(events) => {
+            info!(event = "GetAuditLog", data_id = %payload.username, "Audit log successfully retrieved");
+            Json(AuditLogResponse {
+                success: true,
+                error: None,
+                events: events
+                    .into_iter()
+                    .map(|e| AuditEntry {
+                        event: e.event,
+                        created_at: e.created_at,
+                    })
+                    .collect(),
+            })
+        }
+        Err(e) => {
+            error!(event = "GetAuditLog", data_id = %payload.username, "{}", e);
+            Json(AuditLogResponse {
+                success: false,
+                error: Some(e),
+                events: vec![],
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{test_store, MemoryStore};
+    use axum::http::Request;
+
+    fn parts(headers: &[(&str, &str)]) -> Parts {
+        let mut request = Request::builder();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn test_session_token_extraction() {
+        assert_eq!(
+            bearer_token(&parts(&[("authorization", "Bearer abc")])),
+            Some("abc".to_string())
+        );
+        assert_eq!(
+            bearer_token(&parts(&[("authorization", "Basic abc")])),
+            None
+        );
+        assert_eq!(
+            cookie_token(&parts(&[("cookie", "theme=dark; session=xyz")])),
+            Some("xyz".to_string())
+        );
+        assert_eq!(cookie_token(&parts(&[("cookie", "session=")])), None);
+    }
+
+    #[test]
+    fn test_generate_and_hash_token() {
+        let token = generate_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_token());
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+    }
+
+    #[tokio::test]
+    async fn test_login_and_logout() {
+        let store = test_store(&["test_user", "other_user"]).await;
+        assert!(login(&store, "test_user", "wrong_password", None)
+            .await
+            .is_err());
+        let (token, expires_at) = login(&store, "test_user", "test_password", None)
+            .await
+            .unwrap();
+        assert!(expires_at > unix_now());
+        let session = Credential::Session(token.clone());
+        assert!(
+            authenticate(&store, "test_user", Some(&session), Scope::Delete)
+                .await
+                .is_ok()
+        );
+        // a session only grants access to its own wiki
+        assert!(
+            authenticate(&store, "other_user", Some(&session), Scope::Write)
+                .await
+                .is_err()
+        );
+        assert!(authenticate(&store, "test_user", None, Scope::Write)
+            .await
+            .is_err());
+        logout(&store, &token).await.unwrap();
+        assert!(
+            authenticate(&store, "test_user", Some(&session), Scope::Write)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expired_session() {
+        let store = MemoryStore::new();
+        store
+            .insert_wiki("test_user", "# hello", "<h1>hello</h1>", "hash")
+            .await
+            .unwrap();
+        store
+            .create_session("test_user", &hash_token("old"), unix_now() - 1)
+            .await
+            .unwrap();
+        let session = Credential::Session("old".to_string());
+        assert!(
+            authenticate(&store, "test_user", Some(&session), Scope::Write)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_change_password() {
+        let store = test_store(&["test_user"]).await;
+        let (token, _) = login(&store, "test_user", "test_password", None)
+            .await
+            .unwrap();
+        assert_eq!(
+            change_password(&store, "test_user", "wrong_password", None, "new_password").await,
+            Some("Wrong username or password".to_string())
+        );
+        assert_eq!(
+            change_password(&store, "test_user", "test_password", None, "new_password").await,
+            None
+        );
+        assert!(login(&store, "test_user", "test_password", None)
+            .await
+            .is_err());
+        assert!(login(&store, "test_user", "new_password", None)
+            .await
+            .is_ok());
+        // sessions opened with the old password are revoked
+        let session = Credential::Session(token);
+        assert!(
+            authenticate(&store, "test_user", Some(&session), Scope::Write)
+                .await
+                .is_err()
+        );
+        let events = store.list_audit_events("test_user").await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, PASSWORD_CHANGED);
+    }
+
+    #[test]
+    fn test_lockout_secs() {
+        assert_eq!(lockout_secs(LOCKOUT_THRESHOLD - 1), None);
+        assert_eq!(lockout_secs(LOCKOUT_THRESHOLD), Some(30));
+        assert_eq!(lockout_secs(LOCKOUT_THRESHOLD + 2), Some(120));
+        assert_eq!(lockout_secs(u32::MAX), Some(MAX_LOCKOUT_SECS));
+    }
+
+    #[tokio::test]
+    async fn test_account_lockout() {
+        let store = MemoryStore::new();
+        let hashed = bcrypt::hash("test_password", 4).unwrap();
+        store
+            .insert_wiki("test_user", "# hello", "<h1>hello</h1>", &hashed)
+            .await
+            .unwrap();
+        let wrong = Credential::Password("wrong_password".to_string());
+        let right = Credential::Password("test_password".to_string());
+        for _ in 0..LOCKOUT_THRESHOLD {
+            assert_eq!(
+                authenticate(&store, "test_user", Some(&wrong), Scope::Write)
+                    .await
+                    .err(),
+                Some("Wrong username or password".to_string())
+            );
+        }
+        // even the right password is refused while locked
+        let locked = authenticate(&store, "test_user", Some(&right), Scope::Write)
+            .await
+            .unwrap_err();
+        assert!(locked.starts_with("Too many failed attempts"));
+        // once the lock expires, a success resets the counter
+        store.lock_login("test_user", unix_now() - 1).await.unwrap();
+        assert!(
+            authenticate(&store, "test_user", Some(&right), Scope::Write)
+                .await
+                .is_ok()
+        );
+        assert_eq!(store.get_login_throttle("test_user").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_bcrypt_hash_upgraded_on_login() {
+        let store = MemoryStore::new();
+        let legacy = bcrypt::hash("test_password", 4).unwrap();
+        store
+            .insert_wiki("test_user", "# hello", "<h1>hello</h1>", &legacy)
+            .await
+            .unwrap();
+        assert!(login(&store, "test_user", "test_password", None)
+            .await
+            .is_ok());
+        let upgraded = store.get_wiki("test_user").await.unwrap().unwrap().password;
+        assert!(upgraded.starts_with("$argon2id$"));
+        assert!(login(&store, "test_user", "test_password", None)
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,394 @@
+//! Other accounts the owner of a wiki lets edit it. Editors may read the
+//! source, edit and restore pages; admins may also delete pages. Only the
+//! owner manages collaborators, the visibility and the wiki itself.
+//!
+//! Collaborators act with their own password, session or API key (see
+//! [`crate::auth::authorize`]) and the revisions they make are attributed to
+//! them.
+
+use crate::auth::{authenticate_owner, AuthToken, Credential};
+use crate::store::{Collaborator, Role, StoreError, WikiStore};
+use crate::{AppState, DeleteWikiResponse};
+use axum::{extract::State, response::Json};
+use derivative::Derivative;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, instrument};
+
+/// Gives `collaborator` `role` on the wiki of `username`, replacing the role
+/// they had if any.
+pub async fn grant_role(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    collaborator: &str,
+    role: Role,
+) -> Option<String> {
+    if let Err(e) = authenticate_owner(store, username, credential).await {
+        return Some(e);
+    }
+    if collaborator == username {
+        return Some("The owner of a wiki cannot be one of its collaborators".to_string());
+    }
+    match store.get_wiki(collaborator).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Some(format!("User {} does not exist", collaborator)),
+        Err(e) => return Some(e.to_string()),
+    }
+    store
+        .set_collaborator(username, collaborator, role)
+        .await
+        .err()
+        .map(|e| e.to_string())
+}
+
+pub async fn list_roles(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+) -> Result<Vec<Collaborator>, String> {
+    authenticate_owner(store, username, credential).await?;
+    store
+        .list_collaborators(username)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+pub async fn revoke_role(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    collaborator: &str,
+) -> Option<String> {
+    if let Err(e) = authenticate_owner(store, username, credential).await {
+        return Some(e);
+    }
+    match store.delete_collaborator(username, collaborator).await {
+        Ok(()) => None,
+        Err(StoreError::NotFound) => Some(format!(
+            "User {} is not a collaborator on this wiki",
+            collaborator
+        )),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct GrantRoleRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+    pub collaborator: String,
+    pub role: Role,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct ListCollaboratorsRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct RevokeRoleRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+    pub collaborator: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CollaboratorSummary {
+    pub username: String,
+    pub role: Role,
+    pub created_at: i64,
+}
+
+impl From<Collaborator> for CollaboratorSummary {
+    fn from(collaborator: Collaborator) -> Self {
+        Self {
+            username: collaborator.username,
+            role: collaborator.role,
+            created_at: collaborator.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ListCollaboratorsResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    pub collaborators: Vec<CollaboratorSummary>,
+}
+
+#[instrument(skip(state))]
+pub async fn grant(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<GrantRoleRequest>,
+) -> Json<DeleteWikiResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match grant_role(
+        state.store.as_ref(),
+        &payload.username,
+        credential.as_ref(),
+        &payload.collaborator,
+        payload.role,
+    )
+    .await
+    {
+        Some(e) => {
+            error!(event = "GrantRole", data_id = %payload.username, "{}", e);
+            Json(DeleteWikiResponse {
+                success: false,
+                error: Some(e),
+            })
+        }
+        None => {
+            info!(event = "GrantRole", data_id = %payload.username, "User {} is now {}", payload.collaborator, payload.role.as_str());
+            Json(DeleteWikiResponse {
+                success: true,
+                error: None,
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn list(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<ListCollaboratorsRequest>,
+) -> Json<ListCollaboratorsResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match list_roles(state.store.as_ref(), &payload.username, credential.as_ref()).await {
+        Ok(collaborators) => {
+            info!(event = "ListCollaborators", data_id = %payload.username, "Collaborators successfully retrieved");
+            Json(ListCollaboratorsResponse {
+                success: true,
+                error: None,
+                collaborators: collaborators
+                    .into_iter()
+                    .map(CollaboratorSummary::from)
+                    .collect(),
+            })
+        }
+        Err(e) => {
+            error!(event = "ListCollaborators", data_id = %payload.username, "{}", e);
+            Json(ListCollaboratorsResponse {
+                success: false,
+                error: Some(e),
+                collaborators: vec![],
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn revoke(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<RevokeRoleRequest>,
+) -> Json<DeleteWikiResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match revoke_role(
+        state.store.as_ref(),
+        &payload.username,
+        credential.as_ref(),
+        &payload.collaborator,
+    )
+    .await
+    {
+        Some(e) => {
+            error!(event = "RevokeRole", data_id = %payload.username, "{}", e);
+            Json(DeleteWikiResponse {
+                success: false,
+                error: Some(e),
+            })
+        }
+        None => {
+            info!(event = "RevokeRole", data_id = %payload.username, "User {} is no longer a collaborator", payload.collaborator);
+            Json(DeleteWikiResponse {
+                success: true,
+                error: None,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{authorize, login, Scope};
+    use crate::pages::{delete_page_record, insert_page_record, update_page_record};
+    use crate::store::{test_store, Visibility};
+    use crate::visibility::{authorize_read, set_wiki_visibility};
+
+    #[tokio::test]
+    async fn test_collaborator_roles() {
+        let store = test_store(&["test_user", "editor_user", "stranger"]).await;
+        let password = Credential::Password("test_password".to_string());
+
+        // strangers can neither write nor have themselves added
+        assert!(authorize(
+            &store,
+            "test_user",
+            Some("stranger"),
+            Some(&password),
+            Scope::Write
+        )
+        .await
+        .is_err());
+        assert!(grant_role(
+            &store,
+            "stranger",
+            Some(&password),
+            "test_user",
+            Role::Editor
+        )
+        .await
+        .is_none());
+        assert!(authorize(
+            &store,
+            "test_user",
+            Some("stranger"),
+            Some(&password),
+            Scope::Write
+        )
+        .await
+        .is_err());
+        assert!(
+            grant_role(&store, "test_user", Some(&password), "nobody", Role::Editor)
+                .await
+                .is_some()
+        );
+        assert!(grant_role(
+            &store,
+            "test_user",
+            Some(&password),
+            "test_user",
+            Role::Admin
+        )
+        .await
+        .is_some());
+
+        // editors write with their own password or session, revisions are
+        // attributed to them
+        assert_eq!(
+            grant_role(
+                &store,
+                "test_user",
+                Some(&password),
+                "editor_user",
+                Role::Editor
+            )
+            .await,
+            None
+        );
+        assert_eq!(
+            insert_page_record(
+                &store,
+                "test_user",
+                Some("editor_user"),
+                Some(&password),
+                "notes",
+                "# notes",
+                None
+            )
+            .await,
+            None
+        );
+        let (session, _) = login(&store, "editor_user", "test_password", None)
+            .await
+            .unwrap();
+        let session = Credential::Session(session);
+        assert_eq!(
+            update_page_record(
+                &store,
+                "test_user",
+                None,
+                Some(&session),
+                "notes",
+                "# more notes",
+                None
+            )
+            .await,
+            None
+        );
+        let revisions = store.list_revisions("test_user").await.unwrap();
+        assert_eq!(revisions[0].author.as_deref(), Some("editor_user"));
+        assert_eq!(revisions[1].author.as_deref(), Some("editor_user"));
+        assert_eq!(revisions[2].author.as_deref(), Some("test_user"));
+
+        // deleting pages takes an admin
+        assert!(
+            delete_page_record(&store, "test_user", None, Some(&session), "notes")
+                .await
+                .is_some()
+        );
+        assert_eq!(
+            grant_role(
+                &store,
+                "test_user",
+                Some(&password),
+                "editor_user",
+                Role::Admin
+            )
+            .await,
+            None
+        );
+        assert_eq!(
+            delete_page_record(&store, "test_user", None, Some(&session), "notes").await,
+            None
+        );
+
+        // collaborators can read private wikis
+        assert_eq!(
+            set_wiki_visibility(&store, "test_user", Some(&password), Visibility::Private).await,
+            None
+        );
+        let Credential::Session(token) = &session else {
+            unreachable!()
+        };
+        assert!(
+            authorize_read(&store, "test_user", &AuthToken(Some(token.clone())), None)
+                .await
+                .is_ok()
+        );
+
+        // only the owner manages collaborators
+        assert!(list_roles(&store, "test_user", Some(&session))
+            .await
+            .is_err());
+        let collaborators = list_roles(&store, "test_user", Some(&password))
+            .await
+            .unwrap();
+        assert_eq!(collaborators.len(), 1);
+        assert_eq!(collaborators[0].username, "editor_user");
+        assert_eq!(collaborators[0].role, Role::Admin);
+        assert_eq!(
+            revoke_role(&store, "test_user", Some(&password), "editor_user").await,
+            None
+        );
+        assert!(
+            revoke_role(&store, "test_user", Some(&password), "editor_user")
+                .await
+                .is_some()
+        );
+        assert!(
+            authorize(&store, "test_user", None, Some(&session), Scope::Write)
+                .await
+                .is_err()
+        );
+        assert!(
+            authorize_read(&store, "test_user", &AuthToken(Some(token.clone())), None)
+                .await
+                .is_err()
+        );
+    }
+}
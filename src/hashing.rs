@@ -0,0 +1,164 @@
+//! Password hashing off the async runtime.
+//!
+//! Argon2id (and bcrypt, for legacy hashes) deliberately burn CPU for a while.
+//! Running them inline would block a runtime worker for that long, so a burst
+//! of logins could stall every other request. Instead each hash runs on the
+//! blocking thread pool, at most `PASSWORD_HASH_CONCURRENCY` at a time. Work
+//! waiting longer than `PASSWORD_HASH_QUEUE_TIMEOUT_SECS` for its turn fails.
+
+use argon2::password_hash::{phc::PasswordHash, Error as PasswordHashError, PasswordHasher};
+use argon2::{Argon2, PasswordVerifier};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+const DEFAULT_QUEUE_TIMEOUT_SECS: u64 = 5;
+
+struct HashingPool {
+    permits: Arc<Semaphore>,
+    queue_timeout: Duration,
+}
+
+impl HashingPool {
+    fn new(concurrency: usize, queue_timeout: Duration) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+            queue_timeout,
+        }
+    }
+
+    /// Defaults to one hash per available core, waiting up to 5 seconds.
+    fn from_env() -> Self {
+        let concurrency = std::env::var("PASSWORD_HASH_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(2)
+            });
+        let queue_timeout = std::env::var("PASSWORD_HASH_QUEUE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_QUEUE_TIMEOUT_SECS);
+        Self::new(concurrency, Duration::from_secs(queue_timeout))
+    }
+
+    async fn run<T, F>(&self, work: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, String> + Send + 'static,
+    {
+        let permit = tokio::time::timeout(self.queue_timeout, self.permits.clone().acquire_owned())
+            .await
+            .map_err(|_| "The server is busy, please try again in a moment".to_string())?
+            .map_err(|e| e.to_string())?;
+        // the permit moves into the task, so that it is held until the hash is
+        // done even if the request is cancelled in the meantime
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            work()
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+}
+
+static POOL: LazyLock<HashingPool> = LazyLock::new(HashingPool::from_env);
+
+/// Hashes a password with Argon2id, as a PHC string (`$argon2id$v=19$...`).
+pub async fn hash_password(password: &str) -> Result<String, String> {
+    let password = password.to_string();
+    POOL.run(move || {
+        Argon2::default()
+            .hash_password(password.as_bytes())
+            .map(|h| h.to_string())
+            .map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// Verifies a password against an Argon2 PHC string or, for wikis created
+/// before Argon2id became the default, a bcrypt hash. The format is told by
+/// its prefix.
+pub async fn verify_password(password: &str, hashed_password: &str) -> Result<bool, String> {
+    let password = password.to_string();
+    let hashed_password = hashed_password.to_string();
+    POOL.run(move || {
+        if !hashed_password.starts_with("$argon2") {
+            return bcrypt::verify(&password, &hashed_password).map_err(|e| e.to_string());
+        }
+        let parsed = PasswordHash::new(&hashed_password).map_err(|e| e.to_string())?;
+        match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+            Ok(()) => Ok(true),
+            Err(PasswordHashError::PasswordInvalid) => Ok(false),
+            Err(e) => Err(e.to_string()),
+        }
+    })
+    .await
+}
+
+/// Whether a hash should be replaced by an Argon2id one the next time its
+/// password is known, i.e. on a successful login.
+pub fn needs_rehash(hashed_password: &str) -> bool {
+    !hashed_password.starts_with("$argon2id$")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_hash_and_verify_password() {
+        let hashed = hash_password("test_password").await.unwrap();
+        assert_eq!(verify_password("test_password", &hashed).await, Ok(true));
+        assert_eq!(verify_password("wrong_password", &hashed).await, Ok(false));
+    }
+
+    #[tokio::test]
+    async fn test_argon2_hash() {
+        let password = "test_password";
+        let hashed = hash_password(password)
+            .await
+            .expect("Should be able to hash the password");
+        assert!(hashed.starts_with("$argon2id$"));
+        assert!(!needs_rehash(&hashed));
+        // passwords longer than the 72 bytes bcrypt looks at are told apart
+        let long = "a".repeat(80);
+        let hashed = hash_password(&long)
+            .await
+            .expect("Should be able to hash the password");
+        assert_eq!(verify_password(&"a".repeat(79), &hashed).await, Ok(false));
+    }
+
+    #[tokio::test]
+    async fn test_verify_legacy_bcrypt_hash() {
+        let hashed = bcrypt::hash("test_password", 4).expect("Should be able to hash the password");
+        assert!(needs_rehash(&hashed));
+        assert_eq!(verify_password("test_password", &hashed).await, Ok(true));
+        assert_eq!(verify_password("wrong_password", &hashed).await, Ok(false));
+    }
+
+    #[tokio::test]
+    async fn test_queue_timeout() {
+        let pool = HashingPool::new(1, Duration::from_millis(50));
+        let (release, wait) = std::sync::mpsc::channel::<()>();
+        let busy = pool.run(move || {
+            wait.recv().map_err(|e| e.to_string())?;
+            Ok(())
+        });
+        let queued = async {
+            // give the first job time to take the only permit
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let outcome = pool.run(|| Ok(())).await;
+            release.send(()).unwrap();
+            outcome
+        };
+        let (busy, queued) = tokio::join!(busy, queued);
+        assert_eq!(busy, Ok(()));
+        assert_eq!(
+            queued,
+            Err("The server is busy, please try again in a moment".to_string())
+        );
+    }
+}
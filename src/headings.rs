@@ -0,0 +1,414 @@
+//! Anchors of headings and tables of contents.
+//!
+//! Every heading of the Markdown gets an `id` slugified from its text the way
+//! GitHub does (`## Getting Started` becomes `user-content-getting-started`,
+//! then `user-content-getting-started-1` for the next one with the same text)
+//! and a permalink shown on hover. A paragraph made of `[TOC]` or `{{toc}}`
+//! is replaced by a nested table of contents, which wikis can also have at the
+//! top of every page (see
+//! [`RenderSettings::toc`](crate::store::RenderSettings::toc)).
+//!
+//! Headings are read from the Markdown AST and told apart from the headings
+//! of raw HTML by their position among the heading tags of the rendered HTML,
+//! before it is sanitized. Headings written as raw HTML are left as they are,
+//! except for ids that the generated ones would clash with.
+
+use crate::auth::generate_token;
+use crate::escape_html;
+use crate::wikilinks::tag_length;
+use markdown::{mdast::Node, to_mdast, Options};
+use std::collections::HashSet;
+
+const TOC_MARKERS: &[&str] = &["<p>[TOC]</p>", "<p>{{toc}}</p>"];
+
+/// Start of the footnotes, rendered at the end of the page out of order.
+const FOOTNOTES_START: &str = "<section data-footnotes";
+
+/// Prefix of the ids of headings, which keeps them from clashing with the ids
+/// of the page around the wiki, like the ids the sanitizer lets through.
+const ID_PREFIX: &str = "user-content-";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    pub depth: u8,
+    pub id: String,
+    pub text: String,
+}
+
+/// Lowercases `text`, drops its punctuation and turns its spaces into dashes.
+pub fn slugify(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            c if c.is_whitespace() => Some('-'),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The text of a heading, without the raw HTML in it.
+fn heading_text(node: &Node) -> String {
+    match node {
+        Node::Html(_) => String::new(),
+        _ => match node.children() {
+            Some(children) => children.iter().map(heading_text).collect(),
+            None => node.to_string(),
+        },
+    }
+}
+
+/// Collects the headings of `node` and, for every heading tag the rendered
+/// HTML will have, in order, whether it is one of them (`true`) or one of the
+/// raw HTML (`false`), counted only when raw HTML is kept.
+fn collect(
+    node: &Node,
+    raw_html: bool,
+    used: &mut HashSet<String>,
+    headings: &mut Vec<Heading>,
+    sources: &mut Vec<bool>,
+) {
+    match node {
+        Node::Heading(heading) => {
+            let text = heading_text(node).trim().to_string();
+            let slug = match slugify(&text) {
+                slug if slug.is_empty() => "section".to_string(),
+                slug => slug,
+            };
+            let mut id = slug.clone();
+            let mut n = 0;
+            while !used.insert(id.clone()) {
+                n += 1;
+                id = format!("{}-{}", slug, n);
+            }
+            headings.push(Heading {
+                depth: heading.depth,
+                id,
+                text,
+            });
+            sources.push(true);
+            for child in &heading.children {
+                collect(child, raw_html, used, headings, sources);
+            }
+        }
+        Node::Html(html) if raw_html => {
+            let mut rest = html.value.as_str();
+            while let Some(start) = rest.find('<') {
+                let (tag, after) = rest[start..].split_at(tag_length(&rest[start..]));
+                if opening_heading_depth(tag).is_some() {
+                    sources.push(false);
+                }
+                rest = after;
+            }
+        }
+        // rendered at the end of the page, out of order
+        Node::FootnoteDefinition(_) => {}
+        _ => {
+            for child in node.children().into_iter().flatten() {
+                collect(child, raw_html, used, headings, sources);
+            }
+        }
+    }
+}
+
+/// The depth of a `<hN>` tag, with or without attributes.
+fn opening_heading_depth(tag: &str) -> Option<u8> {
+    let rest = tag.strip_prefix("<h")?;
+    let depth = rest
+        .get(..1)?
+        .parse()
+        .ok()
+        .filter(|d| (1..=6).contains(d))?;
+    match rest[1..].chars().next()? {
+        '>' | '/' => Some(depth),
+        c if c.is_ascii_whitespace() => Some(depth),
+        _ => None,
+    }
+}
+
+/// The depth of a `<hN>` or `</hN>` tag without attributes, and whether it is
+/// a closing one.
+fn heading_tag(tag: &str) -> Option<(u8, bool)> {
+    let (closing, name) = match tag.strip_prefix("</") {
+        Some(name) => (true, name),
+        None => (false, tag.strip_prefix('<')?),
+    };
+    match name.strip_prefix('h')?.strip_suffix('>')?.parse() {
+        Ok(depth @ 1..=6) => Some((depth, closing)),
+        _ => None,
+    }
+}
+
+/// The headings of a page between [`mark_headings`] and [`link_headings`].
+#[derive(Debug)]
+pub struct MarkedHeadings {
+    headings: Vec<Heading>,
+    /// Prefix of the temporary ids, random so that raw HTML cannot forge them.
+    marker: String,
+}
+
+/// Gives the headings of `html`, rendered from `markdown` with `options` and
+/// not sanitized yet, temporary ids that the sanitizer lets through. Heading
+/// tags are told apart by their position among those of the Markdown AST, so
+/// that only the headings of the Markdown are marked, not those of its raw
+/// HTML.
+pub fn mark_headings(
+    html: &str,
+    markdown: &str,
+    options: &Options,
+) -> Result<(String, MarkedHeadings), String> {
+    let root = to_mdast(markdown, &options.parse).map_err(|e| e.to_string())?;
+    let mut headings = Vec::new();
+    let mut sources = Vec::new();
+    collect(
+        &root,
+        options.compile.allow_dangerous_html,
+        &mut HashSet::new(),
+        &mut headings,
+        &mut sources,
+    );
+    let marker = format!("{}{}-", ID_PREFIX, &generate_token()[..16]);
+    let mut out = String::with_capacity(html.len());
+    let mut sources = sources.into_iter();
+    let mut index = 0;
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        let (tag, after) = rest[start..].split_at(tag_length(&rest[start..]));
+        rest = after;
+        if tag.starts_with(FOOTNOTES_START) {
+            out.push_str(tag);
+            break;
+        }
+        let Some(depth) = opening_heading_depth(tag) else {
+            out.push_str(tag);
+            continue;
+        };
+        match sources.next() {
+            Some(true) => {
+                if heading_tag(tag) == Some((depth, false))
+                    && headings.get(index).is_some_and(|h| h.depth == depth)
+                {
+                    out.push_str(&format!("<h{} id=\"{}{}\">", depth, marker, index));
+                } else {
+                    out.push_str(tag);
+                }
+                index += 1;
+            }
+            _ => out.push_str(tag),
+        }
+    }
+    out.push_str(rest);
+    Ok((out, MarkedHeadings { headings, marker }))
+}
+
+/// The depth and index of a heading marked by [`mark_headings`].
+fn marked_heading(tag: &str, marker: &str) -> Option<(u8, usize)> {
+    let depth = opening_heading_depth(tag)?;
+    let index = tag[3..]
+        .strip_prefix(" id=\"")?
+        .strip_prefix(marker)?
+        .strip_suffix("\">")?
+        .parse()
+        .ok()?;
+    Some((depth, index))
+}
+
+/// Replaces the temporary ids of the headings of `html` with their own, and
+/// adds a permalink to them. Ids of the raw HTML that are the same as those
+/// of headings are dropped.
+fn anchor_headings(html: &str, marked: &MarkedHeadings) -> String {
+    let mut html = html.to_string();
+    for heading in &marked.headings {
+        html = html.replace(&format!(" id=\"{}{}\"", ID_PREFIX, heading.id), "");
+    }
+    let mut out = String::with_capacity(html.len());
+    let mut open: Option<&Heading> = None;
+    let mut rest = html.as_str();
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        let (tag, after) = rest[start..].split_at(tag_length(&rest[start..]));
+        let heading = marked_heading(tag, &marked.marker)
+            .and_then(|(depth, index)| marked.headings.get(index).filter(|h| h.depth == depth));
+        match (heading, heading_tag(tag)) {
+            (Some(heading), _) => {
+                out.push_str(&format!(
+                    "<h{} id=\"{}{}\">",
+                    heading.depth, ID_PREFIX, heading.id
+                ));
+                open = Some(heading);
+            }
+            (None, Some((depth, true))) if open.is_some_and(|h| h.depth == depth) => {
+                out.push_str(&format!(
+                    "<a class=\"anchor\" href=\"#{}{}\" aria-label=\"Permalink to this section\">#</a>",
+                    ID_PREFIX,
+                    open.take().unwrap().id
+                ));
+                out.push_str(tag);
+            }
+            _ => out.push_str(tag),
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// A nested list of links to `headings`, empty without headings.
+pub fn render_toc(headings: &[Heading]) -> String {
+    if headings.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from("<nav class=\"toc\">\n<p><strong>Contents</strong></p>\n");
+    // depths of the open lists
+    let mut levels: Vec<u8> = Vec::new();
+    for heading in headings {
+        while levels.last().is_some_and(|&depth| depth > heading.depth) {
+            html.push_str("</li>\n</ul>\n");
+            levels.pop();
+        }
+        match levels.last() {
+            Some(&depth) if depth == heading.depth => html.push_str("</li>\n"),
+            _ => {
+                html.push_str("<ul>\n");
+                levels.push(heading.depth);
+            }
+        }
+        html.push_str(&format!(
+            "<li><a href=\"#{}{}\">{}</a>",
+            ID_PREFIX,
+            heading.id,
+            escape_html(&heading.text)
+        ));
+    }
+    for _ in levels {
+        html.push_str("</li>\n</ul>\n");
+    }
+    html.push_str("</nav>");
+    html
+}
+
+/// Anchors the headings of `html` marked by [`mark_headings`], and puts a
+/// table of contents in place of its markers, or at the top with `toc`.
+pub fn link_headings(html: &str, marked: &MarkedHeadings, toc: bool) -> String {
+    let mut html = anchor_headings(html, marked);
+    let contents = render_toc(&marked.headings);
+    let has_marker = TOC_MARKERS.iter().any(|m| html.contains(m));
+    for marker in TOC_MARKERS {
+        html = html.replace(marker, &contents);
+    }
+    if toc && !has_marker && !contents.is_empty() {
+        html = format!("{}\n{}", contents, html);
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use markdown::{to_html_with_options, CompileOptions};
+
+    /// Renders `markdown` as `render_markdown` does, without sanitizing it.
+    fn render(markdown: &str, raw_html: bool) -> String {
+        let options = Options {
+            compile: CompileOptions {
+                allow_dangerous_html: raw_html,
+                ..CompileOptions::gfm()
+            },
+            ..Options::gfm()
+        };
+        let html = to_html_with_options(markdown, &options).unwrap();
+        let (html, marked) = mark_headings(&html, markdown, &options).unwrap();
+        link_headings(&html, &marked, false)
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Getting Started"), "getting-started");
+        assert_eq!(slugify("What's new in v2.0?"), "whats-new-in-v20");
+        assert_eq!(slugify("  snake_case and-dashes "), "snake_case-and-dashes");
+        assert_eq!(slugify("Über Straße"), "über-straße");
+        assert_eq!(slugify("!!!"), "");
+    }
+
+    #[test]
+    fn test_collect_headings() {
+        let markdown =
+            "# Notes\n\n## Setup\n\n## Setup\n\nSub\n---\n\n> ### `code` and *emphasis*\n\n## ?!";
+        let (_, marked) = mark_headings("", markdown, &Options::gfm()).unwrap();
+        let ids: Vec<(u8, &str, &str)> = marked
+            .headings
+            .iter()
+            .map(|h| (h.depth, h.id.as_str(), h.text.as_str()))
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                (1, "notes", "Notes"),
+                (2, "setup", "Setup"),
+                (2, "setup-1", "Setup"),
+                (2, "sub", "Sub"),
+                (3, "code-and-emphasis", "code and emphasis"),
+                (2, "section", "?!"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_toc() {
+        let heading = |depth, id: &str| Heading {
+            depth,
+            id: id.to_string(),
+            text: id.to_uppercase(),
+        };
+        assert_eq!(render_toc(&[]), "");
+        assert_eq!(
+            render_toc(&[
+                heading(2, "a"),
+                heading(3, "b"),
+                heading(4, "c"),
+                heading(2, "d"),
+                Heading {
+                    depth: 3,
+                    id: "e".to_string(),
+                    text: "E & F".to_string(),
+                },
+            ]),
+            "<nav class=\"toc\">\n<p><strong>Contents</strong></p>\n<ul>\n<li><a href=\"#user-content-a\">A</a><ul>\n<li><a href=\"#user-content-b\">B</a><ul>\n<li><a href=\"#user-content-c\">C</a></li>\n</ul>\n</li>\n</ul>\n</li>\n<li><a href=\"#user-content-d\">D</a><ul>\n<li><a href=\"#user-content-e\">E &amp; F</a></li>\n</ul>\n</li>\n</ul>\n</nav>"
+        );
+    }
+
+    #[test]
+    fn test_link_headings() {
+        assert_eq!(
+            render("## Setup\n\n## Setup", false),
+            "<h2 id=\"user-content-setup\">Setup<a class=\"anchor\" href=\"#user-content-setup\" aria-label=\"Permalink to this section\">#</a></h2>\n<h2 id=\"user-content-setup-1\">Setup<a class=\"anchor\" href=\"#user-content-setup-1\" aria-label=\"Permalink to this section\">#</a></h2>"
+        );
+        assert_eq!(render("x", false), "<p>x</p>");
+    }
+
+    #[test]
+    fn test_raw_headings() {
+        // raw headings before the Markdown ones keep no ids, whatever their text
+        assert_eq!(
+            render("<h2>Setup</h2>\n\n## Setup", true),
+            "<h2>Setup</h2>\n<h2 id=\"user-content-setup\">Setup<a class=\"anchor\" href=\"#user-content-setup\" aria-label=\"Permalink to this section\">#</a></h2>"
+        );
+        // nor do headings in attribute values or with attributes
+        assert_eq!(
+            render("<img alt=\"<h2>\"><h2 align=\"center\">Setup</h2>\n\n## Setup", true),
+            "<p><img alt=\"<h2>\"><h2 align=\"center\">Setup</h2></p>\n<h2 id=\"user-content-setup\">Setup<a class=\"anchor\" href=\"#user-content-setup\" aria-label=\"Permalink to this section\">#</a></h2>"
+        );
+        // ids written by the author cannot clash with those of headings
+        assert_eq!(
+            render("<p id=\"user-content-setup\">x</p>\n\n## Setup", true),
+            "<p>x</p>\n<h2 id=\"user-content-setup\">Setup<a class=\"anchor\" href=\"#user-content-setup\" aria-label=\"Permalink to this section\">#</a></h2>"
+        );
+        // nor can temporary ids be forged
+        assert_eq!(
+            render("<h2 id=\"user-content-0\">x</h2>\n\n## Setup", true),
+            "<h2 id=\"user-content-0\">x</h2>\n<h2 id=\"user-content-setup\">Setup<a class=\"anchor\" href=\"#user-content-setup\" aria-label=\"Permalink to this section\">#</a></h2>"
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Named API keys, so that scripts and CI pipelines can publish a wiki without
+//! knowing the account password.
+//!
+//! Keys are minted, listed and revoked by the owner with their password or a
+//! session, and are then sent as `Authorization: Bearer` tokens.
+
+use crate::auth::{authenticate_owner, generate_token, hash_token, AuthToken, Credential, Scope};
+use crate::store::{ApiKey, StoreError, WikiStore};
+use crate::{AppState, DeleteWikiResponse};
+use axum::{extract::State, response::Json};
+use derivative::Derivative;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, instrument};
+
+/// Prefix telling API keys apart from session tokens.
+pub const API_KEY_PREFIX: &str = "pwk_";
+const MAX_KEY_NAME_LENGTH: usize = 64;
+
+/// Creates a key with `scopes` for the wiki of `username`, returning it with
+/// its secret. The secret is not stored and cannot be shown again.
+pub async fn mint_api_key(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    name: &str,
+    scopes: &[Scope],
+) -> Result<(ApiKey, String), String> {
+    authenticate_owner(store, username, credential).await?;
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_KEY_NAME_LENGTH {
+        return Err(format!(
+            "API key names must be between 1 and {} characters long",
+            MAX_KEY_NAME_LENGTH
+        ));
+    }
+    if scopes.is_empty() {
+        return Err("API keys need at least one scope".to_string());
+    }
+    let mut scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_string()).collect();
+    scopes.sort();
+    scopes.dedup();
+    let secret = format!("{}{}", API_KEY_PREFIX, generate_token());
+    match store
+        .create_api_key(username, name, &hash_token(&secret), &scopes)
+        .await
+    {
+        Ok(key) => Ok((key, secret)),
+        Err(StoreError::AlreadyExists) => {
+            Err(format!("An API key named '{}' already exists", name))
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn list_api_keys(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+) -> Result<Vec<ApiKey>, String> {
+    authenticate_owner(store, username, credential).await?;
+    store
+        .list_api_keys(username)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+pub async fn revoke_api_key(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    id: i64,
+) -> Option<String> {
+    if let Err(e) = authenticate_owner(store, username, credential).await {
+        return Some(e);
+    }
+    match store.delete_api_key(username, id).await {
+        Ok(()) => None,
+        Err(StoreError::NotFound) => Some(format!("API key {} does not exist", id)),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct CreateApiKeyRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct ListApiKeysRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct RevokeApiKeyRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+    pub id: i64,
+}
+
+/// An API key as shown to its owner, without its secret.
+#[derive(Serialize, Debug)]
+pub struct ApiKeySummary {
+    pub id: i64,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+}
+
+impl From<ApiKey> for ApiKeySummary {
+    fn from(key: ApiKey) -> Self {
+        Self {
+            id: key.id,
+            name: key.name,
+            scopes: key.scopes,
+            created_at: key.created_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+#[derive(Serialize, Derivative)]
+#[derivative(Debug)]
+pub struct CreateApiKeyResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    /// The secret to send as a bearer token, only returned once.
+    #[derivative(Debug = "ignore")]
+    pub key: Option<String>,
+    pub api_key: Option<ApiKeySummary>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ListApiKeysResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    pub keys: Vec<ApiKeySummary>,
+}
+
+#[instrument(skip(state))]
+pub async fn create_key(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Json<CreateApiKeyResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match mint_api_key(
+        state.store.as_ref(),
+        &payload.username,
+        credential.as_ref(),
+        &payload.name,
+        &payload.scopes,
+    )
+    .await
+    {
+        Ok((key, secret)) => {
+            info!(event = "CreateApiKey", data_id = %payload.username, "API key {} successfully created", key.id);
+            Json(CreateApiKeyResponse {
+                success: true,
+                error: None,
+                key: Some(secret),
+                api_key: Some(key.into()),
+            })
+        }
+        Err(e) => {
+            error!(event = "CreateApiKey", data_id = %payload.username, "{}", e);
+            Json(CreateApiKeyResponse {
+                success: false,
+                error: Some(e),
+                key: None,
+                api_key: None,
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn list_keys(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<ListApiKeysRequest>,
+) -> Json<ListApiKeysResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match list_api_keys(state.store.as_ref(), &payload.username, credential.as_ref()).await {
+        Ok(keys) => {
+            info!(event = "ListApiKeys", data_id = %payload.username, "API keys successfully retrieved");
+            Json(ListApiKeysResponse {
+                success: true,
+                error: None,
+                keys: keys.into_iter().map(ApiKeySummary::from).collect(),
+            })
+        }
+        Err(e) => {
+            error!(event = "ListApiKeys", data_id = %payload.username, "{}", e);
+            Json(ListApiKeysResponse {
+                success: false,
+                error: Some(e),
+                keys: vec![],
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn revoke_key(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<RevokeApiKeyRequest>,
+) -> Json<DeleteWikiResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match revoke_api_key(
+        state.store.as_ref(),
+        &payload.username,
+        credential.as_ref(),
+        payload.id,
+    )
+    .await
+    {
+        Some(e) => {
+            error!(event = "RevokeApiKey", data_id = %payload.username, "{}", e);
+            Json(DeleteWikiResponse {
+                success: false,
+                error: Some(e),
+            })
+        }
+        None => {
+            info!(event = "RevokeApiKey", data_id = %payload.username, "API key {} successfully revoked", payload.id);
+            Json(DeleteWikiResponse {
+                success: true,
+                error: None,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::authenticate;
+    use crate::store::test_store;
+
+    #[tokio::test]
+    async fn test_api_key_lifecycle() {
+        let store = test_store(&["test_user", "other_user"]).await;
+        let password = Credential::Password("test_password".to_string());
+        let (key, secret) = mint_api_key(
+            &store,
+            "test_user",
+            Some(&password),
+            "ci",
+            &[Scope::Write, Scope::ReadSource],
+        )
+        .await
+        .unwrap();
+        assert!(secret.starts_with(API_KEY_PREFIX));
+        assert_eq!(key.scopes, vec!["read-source", "write"]);
+        assert_eq!(
+            mint_api_key(&store, "test_user", Some(&password), "ci", &[Scope::Write])
+                .await
+                .err(),
+            Some("An API key named 'ci' already exists".to_string())
+        );
+
+        let token = AuthToken(Some(secret.clone()));
+        let api_key = Credential::resolve(None, &token);
+        assert!(matches!(api_key, Some(Credential::ApiKey(_))));
+        let api_key = api_key.as_ref();
+        // scopes are enforced and only the owner's wiki is reachable
+        assert!(authenticate(&store, "test_user", api_key, Scope::Write)
+            .await
+            .is_ok());
+        assert_eq!(
+            authenticate(&store, "test_user", api_key, Scope::Delete)
+                .await
+                .err(),
+            Some("This API key lacks the 'delete' scope".to_string())
+        );
+        assert!(authenticate(&store, "other_user", api_key, Scope::Write)
+            .await
+            .is_err());
+        // keys cannot mint more keys
+        assert!(
+            mint_api_key(&store, "test_user", api_key, "more", &[Scope::Delete])
+                .await
+                .is_err()
+        );
+
+        let keys = list_api_keys(&store, "test_user", Some(&password))
+            .await
+            .unwrap();
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].last_used_at.is_some());
+
+        assert_eq!(
+            revoke_api_key(&store, "test_user", Some(&password), key.id).await,
+            None
+        );
+        assert!(authenticate(&store, "test_user", api_key, Scope::Write)
+            .await
+            .is_err());
+    }
+}
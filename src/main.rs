@@ -1,23 +1,46 @@
-use axum::http::header::CONTENT_TYPE;
+use auth::{AuthToken, Credential, Scope};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
 use axum::http::method::Method;
 use axum::{
-    extract::Path,
+    extract::{Path, Query, State},
     response::{Html, Json},
     routing::{get, post},
     Router,
 };
-use bcrypt::{hash, verify, DEFAULT_COST};
 use derivative::Derivative;
 use http::HeaderValue;
-use libsql::{params, Builder};
-use markdown::to_html;
+use render::{render_for, render_markdown};
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
+use store::{
+    find_duplicate_users, resolve_duplicate_users, DatabaseConfig, LibsqlStore, MemoryStore,
+    RenderSettings, StoreError, WikiStore,
+};
 use tower_governor::{governor::GovernorConfigBuilder, GovernorLayer};
 use tower_http::cors::CorsLayer;
 use tower_http::services::{ServeDir, ServeFile};
 use tracing::{error, info, instrument};
+use usernames::{UsernameError, ValidationError};
+use visibility::{authorize_read, carry_share, check_read_access, ReadParams};
+use wikilinks::mark_missing_links;
+
+mod auth;
+mod collaborators;
+mod hashing;
+mod headings;
+mod keys;
+mod pages;
+mod recovery;
+mod render;
+mod sanitize;
+mod search;
+mod store;
+mod totp;
+mod usernames;
+mod visibility;
+mod wikilinks;
 
 const CSS_STYLE: &str = r#"<style>
   .wiki-container * {
@@ -92,6 +115,38 @@ const CSS_STYLE: &str = r#"<style>
     border-bottom-color: #0066cc;
   }
 
+  /* Permalinks of headings, shown on hover */
+  .wiki-container .anchor {
+    margin-left: 8px;
+    color: #adb5bd;
+    border-bottom: none;
+    opacity: 0;
+    transition: opacity 0.2s ease;
+  }
+
+  .wiki-container :is(h1, h2, h3, h4, h5, h6):hover .anchor,
+  .wiki-container .anchor:focus {
+    opacity: 1;
+  }
+
+  /* Table of contents */
+  .wiki-container .toc {
+    background: #f7f6f3;
+    border-radius: 3px;
+    padding: 12px 16px;
+    margin: 0 0 16px 0;
+  }
+
+  .wiki-container .toc ul {
+    margin: 0;
+  }
+
+  /* Links to pages that do not exist yet */
+  .wiki-container a.wikilink-missing {
+    color: #d9480f;
+    border-bottom: 1px dashed rgba(217, 72, 15, 0.5);
+  }
+
   /* Lists */
   .wiki-container ul, 
   .wiki-container ol {
@@ -144,6 +199,7 @@ const NAVBAR: &str = r#"
   <div class="flex-none">
       <ul class="menu menu-horizontal px-1 gap-2">
           <li><a href="/" class="btn btn-ghost btn-sm">Home</a></li>
+          <li><a href="/search" class="btn btn-ghost btn-sm">Search</a></li>
           <li><a href="https://github.com/AstraBert/personal-wiki" target="_blank" class="btn btn-ghost btn-sm">GitHub</a></li>
       </ul>
   </div>
@@ -152,165 +208,224 @@ const NAVBAR: &str = r#"
 "#;
 
 fn style_html(html: &str, username: &str) -> String {
-    format!(
-        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"UTF-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">\n<title>{}'s Wiki</title>\n<script src=\"https://cdn.jsdelivr.net/npm/@tailwindcss/browser@4\"></script>\n<link href=\"https://cdn.jsdelivr.net/npm/daisyui@5/dist/full.css\" rel=\"stylesheet\" type=\"text/css\" />\n{}\n</head>\n<body>\n{}\n<div class=\"flex flex-col px-6 py-12 items-center justify-center wiki-container\">\n{}\n</div>\n</body>\n</html>",
-        username, CSS_STYLE, NAVBAR, html
-    )
+    style_page(html, &format!("{}'s Wiki", username))
 }
 
-fn hash_pwd(password: &str) -> Result<String, bcrypt::BcryptError> {
-    hash(password, DEFAULT_COST)
+fn style_page(html: &str, title: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"UTF-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">\n<title>{}</title>\n<script src=\"https://cdn.jsdelivr.net/npm/@tailwindcss/browser@4\"></script>\n<link href=\"https://cdn.jsdelivr.net/npm/daisyui@5/dist/full.css\" rel=\"stylesheet\" type=\"text/css\" />\n{}\n</head>\n<body>\n{}\n<div class=\"flex flex-col px-6 py-12 items-center justify-center wiki-container\">\n{}\n</div>\n</body>\n</html>",
+        title, CSS_STYLE, NAVBAR, html
+    )
 }
 
-fn verify_hashed_pwd(password: &str, hashed_password: &str) -> Result<bool, bcrypt::BcryptError> {
-    verify(password, hashed_password)
+/// Escapes text for safe inclusion in HTML content and attribute values.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
 }
 
-struct Wiki {
-    content: String,
-    password: String,
+/// Application state shared by every handler.
+#[derive(Clone)]
+struct AppState {
+    store: Arc<dyn WikiStore>,
 }
 
-impl Wiki {
-    fn new(content: String, password: String) -> Self {
-        Self { content, password }
+impl AppState {
+    fn new(store: Arc<dyn WikiStore>) -> Self {
+        Self { store }
     }
-}
 
-async fn create_table() {
-    let url =
-        std::env::var("LIBSQL_CONNECTION_STRING").expect("LIBSQL_CONNECTION_STRING should be set");
-    let token = std::env::var("LIBSQL_AUTH_TOKEN").expect("LIBSQL_AUTH_TOKEN should be set");
-    let db = Builder::new_remote(url, token)
-        .build()
-        .await
-        .expect("It should be possible to connect to remote database");
-    let conn = db
-        .connect()
-        .expect("It should be possible to connect to a local database");
-
-    // Create a table
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS wikis (id INTEGER PRIMARY KEY, user TEXT, content TEXT, password TEXT)",
-        ()
-    ).await.expect("It should be possible to create a table within the database");
+    /// Builds the store selected by `STORAGE_BACKEND`: `libsql` (the default,
+    /// configured through [`DatabaseConfig::from_env`]) or `memory`.
+    async fn from_env() -> Result<Self, String> {
+        let backend = std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "libsql".to_string());
+        let store: Arc<dyn WikiStore> = match backend.as_str() {
+            "libsql" => {
+                let config = DatabaseConfig::from_env()?;
+                Arc::new(
+                    LibsqlStore::open(&config)
+                        .await
+                        .map_err(|e| e.to_string())?,
+                )
+            }
+            "memory" => Arc::new(MemoryStore::new()),
+            other => {
+                return Err(format!(
+                    "Unsupported STORAGE_BACKEND '{}', expected 'libsql' or 'memory'",
+                    other
+                ))
+            }
+        };
+        Ok(Self::new(store))
+    }
 }
 
-async fn get_record(username: &str) -> Option<Wiki> {
-    let url =
-        std::env::var("LIBSQL_CONNECTION_STRING").expect("LIBSQL_CONNECTION_STRING should be set");
-    let token = std::env::var("LIBSQL_AUTH_TOKEN").expect("LIBSQL_AUTH_TOKEN should be set");
-    let db = Builder::new_remote(url, token).build().await.ok()?;
-    let conn = db.connect().ok()?;
-
-    let mut rows = conn
-        .query(
-            "SELECT content, password FROM wikis WHERE user = ?",
-            params![username],
-        )
-        .await
-        .ok()?;
-
-    if let Some(row) = rows.next().await.ok()? {
-        let content: String = row.get(0).ok()?;
-        let pwd: String = row.get(1).ok()?;
-        return Some(Wiki::new(content, pwd));
+/// URL of a page of a wiki, the home page being the empty path.
+fn page_url(username: &str, page: &str) -> String {
+    if page.is_empty() {
+        format!("/wikis/{}", username)
+    } else {
+        format!("/wikis/{}/{}", username, page)
     }
-
-    None
 }
-async fn insert_record(markdown_text: &str, username: &str, password: &str) -> Option<String> {
-    create_table().await;
-    let url =
-        std::env::var("LIBSQL_CONNECTION_STRING").expect("LIBSQL_CONNECTION_STRING should be set");
-    let token = std::env::var("LIBSQL_AUTH_TOKEN").expect("LIBSQL_AUTH_TOKEN should be set");
-    let db = Builder::new_remote(url, token).build().await.ok()?;
-    let conn = db.connect().ok()?;
-    let html_text = to_html(markdown_text);
+
+async fn insert_record(
+    store: &dyn WikiStore,
+    markdown_text: &str,
+    username: &str,
+    password: &str,
+) -> Option<String> {
+    let html_text = match render_markdown(markdown_text, username, &RenderSettings::default()) {
+        Ok(html) => html,
+        Err(e) => return Some(e),
+    };
     if html_text != markdown_text {
         // conversion happened correctly
-        let user_exists = get_record(username).await;
-        match user_exists {
-            Some(_) => return Some("User already exists".to_string()),
-            None => {
-                conn.execute(
-                    "INSERT INTO wikis (user, content, password) VALUES (?1, ?2, ?3)",
-                    [username, &html_text, password],
-                )
-                .await
-                .ok()?;
-                return None;
-            }
-        }
+        // uniqueness is enforced by the store, so concurrent creations cannot both succeed
+        return match store
+            .insert_wiki(username, markdown_text, &html_text, password)
+            .await
+        {
+            Ok(()) => None,
+            Err(StoreError::AlreadyExists) => Some("Username is already taken".to_string()),
+            Err(e) => Some(e.to_string()),
+        };
     }
     Some("Could not convert markdown text to HTML".to_string())
 }
-async fn update_record(markdown_text: &str, username: &str, password: &str) -> Option<String> {
-    create_table().await;
-    let url =
-        std::env::var("LIBSQL_CONNECTION_STRING").expect("LIBSQL_CONNECTION_STRING should be set");
-    let token = std::env::var("LIBSQL_AUTH_TOKEN").expect("LIBSQL_AUTH_TOKEN should be set");
-    let db = Builder::new_remote(url, token).build().await.ok()?;
-    let conn = db.connect().ok()?;
-    let html_text = to_html(markdown_text);
+async fn update_record(
+    store: &dyn WikiStore,
+    markdown_text: &str,
+    username: &str,
+    account: Option<&str>,
+    credential: Option<&Credential>,
+    summary: Option<&str>,
+) -> Option<String> {
+    let author = match auth::authorize(store, username, account, credential, Scope::Write).await {
+        Ok(author) => author,
+        Err(e) => return Some(e),
+    };
+    let html_text = match render_for(store, username, markdown_text).await {
+        Ok(html) => html,
+        Err(e) => return Some(e),
+    };
     if html_text != markdown_text {
         // conversion happened correctly
-        let user_exists = get_record(username).await;
-        match user_exists {
-            Some(r) => {
-                let verification = verify_hashed_pwd(password, &r.password);
-                match verification {
-                    Ok(pwd_match) => {
-                        if pwd_match {
-                            conn.execute(
-                                "UPDATE wikis SET content = ?1 WHERE user = ?2",
-                                [&html_text, username],
-                            )
-                            .await
-                            .ok()?;
-                            return None;
-                        } else {
-                            return Some("Wrong username or password".to_string());
-                        }
-                    }
-                    Err(e) => {
-                        return Some(e.to_string());
-                    }
-                }
-            }
-            None => return Some("User does not exists".to_string()),
-        }
+        return store
+            .update_wiki(username, markdown_text, &html_text, summary, &author)
+            .await
+            .err()
+            .map(|e| e.to_string());
     }
     Some("Could not convert markdown text to HTML".to_string())
 }
 
-async fn delete_record(username: &str, password: &str) -> Option<String> {
-    create_table().await;
-    let url =
-        std::env::var("LIBSQL_CONNECTION_STRING").expect("LIBSQL_CONNECTION_STRING should be set");
-    let token = std::env::var("LIBSQL_AUTH_TOKEN").expect("LIBSQL_AUTH_TOKEN should be set");
-    let db = Builder::new_remote(url, token).build().await.ok()?;
-    let conn = db.connect().ok()?;
-    let user_exists = get_record(username).await;
-    match user_exists {
-        Some(r) => {
-            let verification = verify_hashed_pwd(password, &r.password);
-            match verification {
-                Ok(pwd_match) => {
-                    if pwd_match {
-                        conn.execute("DELETE FROM wikis WHERE user = ?", params![username])
-                            .await
-                            .ok()?;
-                    } else {
-                        return Some("Wrong username or password".to_string());
-                    }
-                }
-                Err(e) => return Some(e.to_string()),
+async fn delete_record(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+) -> Option<String> {
+    if let Err(e) = auth::authenticate(store, username, credential, Scope::Delete).await {
+        return Some(e);
+    }
+    store
+        .delete_wiki(username)
+        .await
+        .err()
+        .map(|e| e.to_string())
+}
+
+/// Makes an old revision the new head of the wiki, recording the restore as a
+/// revision of its own so that nothing is lost.
+async fn restore_record(
+    store: &dyn WikiStore,
+    username: &str,
+    account: Option<&str>,
+    credential: Option<&Credential>,
+    revision_id: i64,
+) -> Option<String> {
+    let author = match auth::authorize(store, username, account, credential, Scope::Write).await {
+        Ok(author) => author,
+        Err(e) => return Some(e),
+    };
+    let revision = match store.get_revision(username, revision_id).await {
+        Ok(Some(r)) => r,
+        Ok(None) => return Some(format!("Revision {} does not exist", revision_id)),
+        Err(e) => return Some(e.to_string()),
+    };
+    let summary = format!("Restored revision {}", revision_id);
+    let html_text = match render_for(store, username, &revision.markdown).await {
+        Ok(html) => html,
+        Err(e) => return Some(e),
+    };
+    let restored = if revision.page.is_empty() {
+        store
+            .update_wiki(
+                username,
+                &revision.markdown,
+                &html_text,
+                Some(&summary),
+                &author,
+            )
+            .await
+    } else {
+        // the page may have been deleted since, in which case it is recreated
+        match store
+            .update_page(
+                username,
+                &revision.page,
+                &revision.markdown,
+                &html_text,
+                Some(&summary),
+                &author,
+            )
+            .await
+        {
+            Err(StoreError::NotFound) => {
+                store
+                    .insert_page(
+                        username,
+                        &revision.page,
+                        &revision.markdown,
+                        &html_text,
+                        Some(&summary),
+                        &author,
+                    )
+                    .await
             }
+            other => other,
         }
-        None => return Some("User does not exist".to_string()),
-    }
-    None
+    };
+    restored.err().map(|e| e.to_string())
+}
+
+/// Returns the Markdown source of a wiki to its owner or an editor, so it can
+/// be edited.
+async fn get_source(
+    store: &dyn WikiStore,
+    username: &str,
+    account: Option<&str>,
+    credential: Option<&Credential>,
+) -> Result<String, String> {
+    auth::authorize(store, username, account, credential, Scope::ReadSource).await?;
+    let wiki = match store.get_wiki(username).await {
+        Ok(Some(w)) => w,
+        Ok(None) => return Err("User does not exist".to_string()),
+        Err(e) => return Err(e.to_string()),
+    };
+    wiki.markdown.ok_or_else(|| {
+        "The Markdown source of this wiki is not available yet, please try again later".to_string()
+    })
 }
 
 #[derive(Deserialize, Derivative)]
@@ -318,15 +433,32 @@ async fn delete_record(username: &str, password: &str) -> Option<String> {
 struct CreateOrUpdateWikiRequest {
     content: String,
     username: String,
+    /// Required on creation, optional on update when a session token is sent.
     #[derivative(Debug = "ignore")]
-    password: String,
+    #[serde(default)]
+    password: Option<String>,
+    /// Account to sign in as with a password when editing someone else's
+    /// wiki as a collaborator, the owner by default.
+    #[serde(default)]
+    account: Option<String>,
+    /// Optional edit summary stored with the revision.
+    #[serde(default)]
+    summary: Option<String>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Derivative)]
+#[derivative(Debug)]
 struct CreateOrUpdateWikiResponse {
     success: bool,
     error: Option<String>,
     url: Option<String>,
+    /// One-time recovery codes, only returned when a wiki is created.
+    #[derivative(Debug = "ignore")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recovery_codes: Option<Vec<String>>,
+    /// Which rule a refused username broke, next to the error message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation: Option<ValidationError>,
 }
 
 impl CreateOrUpdateWikiResponse {
@@ -335,6 +467,15 @@ impl CreateOrUpdateWikiResponse {
             success,
             error,
             url,
+            recovery_codes: None,
+            validation: None,
+        }
+    }
+
+    fn invalid(error: UsernameError) -> Self {
+        Self {
+            validation: Some(error.clone().into()),
+            ..Self::new(false, Some(error.to_string()), None)
         }
     }
 }
@@ -344,7 +485,8 @@ impl CreateOrUpdateWikiResponse {
 struct DeleteWikiRequest {
     username: String,
     #[derivative(Debug = "ignore")]
-    password: String,
+    #[serde(default)]
+    password: Option<String>,
 }
 
 #[derive(Serialize, Debug)]
@@ -353,15 +495,96 @@ struct DeleteWikiResponse {
     error: Option<String>,
 }
 
-#[instrument]
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+struct RestoreRevisionRequest {
+    username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    password: Option<String>,
+    /// Account to sign in as with a password when editing someone else's
+    /// wiki as a collaborator, the owner by default.
+    #[serde(default)]
+    account: Option<String>,
+    revision: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct RevisionSummary {
+    id: i64,
+    /// Path of the page, empty for the home page.
+    page: String,
+    created_at: i64,
+    summary: Option<String>,
+    /// Account that made the revision, unknown for older revisions.
+    author: Option<String>,
+    url: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ListRevisionsResponse {
+    success: bool,
+    error: Option<String>,
+    revisions: Vec<RevisionSummary>,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+struct WikiSourceRequest {
+    username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    password: Option<String>,
+    /// Account to sign in as with a password when editing someone else's
+    /// wiki as a collaborator, the owner by default.
+    #[serde(default)]
+    account: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct WikiSourceResponse {
+    success: bool,
+    error: Option<String>,
+    content: Option<String>,
+}
+
+#[instrument(skip(state))]
 async fn create_wiki(
+    State(state): State<AppState>,
     Json(payload): Json<CreateOrUpdateWikiRequest>,
 ) -> Json<CreateOrUpdateWikiResponse> {
-    let hashed_psw = hash_pwd(&payload.password);
+    let Some(password) = payload.password.as_deref().filter(|p| !p.is_empty()) else {
+        error!(event = "CreateWiki", data_id = %payload.username, "Missing password");
+        return Json(CreateOrUpdateWikiResponse::new(
+            false,
+            Some("A password is required to create a wiki".to_string()),
+            None,
+        ));
+    };
+    // the normalized form is the one stored and used from now on
+    let username = match usernames::normalize_username(&payload.username) {
+        Ok(u) => u,
+        Err(e) => {
+            error!(event = "CreateWiki", data_id = %payload.username, "{}", e);
+            return Json(CreateOrUpdateWikiResponse::invalid(e));
+        }
+    };
+    match usernames::check_available(state.store.as_ref(), &username).await {
+        Ok(None) => {}
+        Ok(Some(e)) => {
+            error!(event = "CreateWiki", data_id = %username, "{}", e);
+            return Json(CreateOrUpdateWikiResponse::invalid(e));
+        }
+        Err(e) => {
+            error!(event = "CreateWiki", data_id = %username, "{}", e);
+            return Json(CreateOrUpdateWikiResponse::new(false, Some(e), None));
+        }
+    }
+    let hashed_psw = hashing::hash_password(password).await;
     let password: String = match hashed_psw {
         Ok(s) => s,
         Err(e) => {
-            error!(event = "CreateWiki", data_id = %payload.username, "{}", e.to_string());
+            error!(event = "CreateWiki", data_id = %username, "{}", e.to_string());
             return Json(CreateOrUpdateWikiResponse::new(
                 false,
                 Some(e.to_string()),
@@ -369,28 +592,46 @@ async fn create_wiki(
             ));
         }
     };
-    if let Some(error_msg) = insert_record(&payload.content, &payload.username, &password).await {
-        error!(event = "CreateWiki", data_id = %payload.username, "{}", error_msg);
+    if let Some(error_msg) =
+        insert_record(state.store.as_ref(), &payload.content, &username, &password).await
+    {
+        error!(event = "CreateWiki", data_id = %username, "{}", error_msg);
         return Json(CreateOrUpdateWikiResponse::new(
             false,
             Some(error_msg),
             None,
         ));
     }
-    info!(event = "CreateWiki", data_id = %payload.username, "Wiki successfully created");
-    Json(CreateOrUpdateWikiResponse::new(
-        true,
-        None,
-        Some(format!("/wikis/{}", &payload.username)),
-    ))
+    info!(event = "CreateWiki", data_id = %username, "Wiki successfully created");
+    let mut response =
+        CreateOrUpdateWikiResponse::new(true, None, Some(format!("/wikis/{}", &username)));
+    // the wiki exists at this point, so a failure here is not fatal: the owner
+    // can generate a new set of codes later on
+    match recovery::issue_recovery_codes(state.store.as_ref(), &username).await {
+        Ok(codes) => response.recovery_codes = Some(codes),
+        Err(e) => {
+            error!(event = "CreateWiki", data_id = %username, "Could not issue recovery codes: {}", e)
+        }
+    }
+    Json(response)
 }
 
-#[instrument]
+#[instrument(skip(state))]
 async fn update_wiki(
+    State(state): State<AppState>,
+    token: AuthToken,
     Json(payload): Json<CreateOrUpdateWikiRequest>,
 ) -> Json<CreateOrUpdateWikiResponse> {
-    if let Some(error_msg) =
-        update_record(&payload.content, &payload.username, &payload.password).await
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    if let Some(error_msg) = update_record(
+        state.store.as_ref(),
+        &payload.content,
+        &payload.username,
+        payload.account.as_deref(),
+        credential.as_ref(),
+        payload.summary.as_deref(),
+    )
+    .await
     {
         error!(event = "UpdateWiki", data_id = %payload.username, "{}", error_msg);
         return Json(CreateOrUpdateWikiResponse::new(
@@ -407,27 +648,59 @@ async fn update_wiki(
     ))
 }
 
-#[instrument]
-async fn get_wiki(Path(username): Path<String>) -> Html<String> {
-    match get_record(&username).await {
-        Some(content) => {
-            let styled_content = style_html(&content.content, &username);
+#[instrument(skip(state))]
+async fn get_wiki(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    token: AuthToken,
+    Query(params): Query<ReadParams>,
+) -> Html<String> {
+    match state.store.get_wiki(&username).await {
+        Ok(Some(content)) => {
+            if let Err(e) = check_read_access(
+                state.store.as_ref(),
+                &username,
+                content.visibility,
+                &token,
+                params.share.as_deref(),
+            )
+            .await
+            {
+                error!(event = "GetWiki", data_id = %username, "{}", e);
+                return Html(escape_html(&e));
+            }
+            let html =
+                mark_missing_links(state.store.as_ref(), &content.content, &username, &token).await;
+            let html = carry_share(&html, &username, params.share.as_deref());
+            let styled_content = style_html(&html, &username);
             info!(event = "GetWiki", data_id = %username, "Wiki successfully retrieved");
             return Html(styled_content);
         }
-        None => {
+        Ok(None) => {
             error!(event = "GetWiki", data_id = %username, "Wiki not found for user {}", username);
             return Html(format!(
                 "Wiki for user {} not found... Please create one and try again!",
-                &username
+                escape_html(&username)
+            ));
+        }
+        Err(e) => {
+            error!(event = "GetWiki", data_id = %username, "{}", e);
+            return Html(format!(
+                "Wiki for user {} could not be loaded... Please try again later!",
+                escape_html(&username)
             ));
         }
     }
 }
 
-#[instrument]
-async fn delete_wiki(Json(payload): Json<DeleteWikiRequest>) -> Json<DeleteWikiResponse> {
-    match delete_record(&payload.username, &payload.password).await {
+#[instrument(skip(state))]
+async fn delete_wiki(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<DeleteWikiRequest>,
+) -> Json<DeleteWikiResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match delete_record(state.store.as_ref(), &payload.username, credential.as_ref()).await {
         Some(s) => {
             error!(event = "DeleteWiki", data_id = %payload.username, "{}", s);
             return Json(DeleteWikiResponse {
@@ -445,10 +718,255 @@ async fn delete_wiki(Json(payload): Json<DeleteWikiRequest>) -> Json<DeleteWikiR
     }
 }
 
+#[instrument(skip(state))]
+async fn restore_wiki(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<RestoreRevisionRequest>,
+) -> Json<CreateOrUpdateWikiResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    if let Some(error_msg) = restore_record(
+        state.store.as_ref(),
+        &payload.username,
+        payload.account.as_deref(),
+        credential.as_ref(),
+        payload.revision,
+    )
+    .await
+    {
+        error!(event = "RestoreWiki", data_id = %payload.username, "{}", error_msg);
+        return Json(CreateOrUpdateWikiResponse::new(
+            false,
+            Some(error_msg),
+            None,
+        ));
+    }
+    info!(event = "RestoreWiki", data_id = %payload.username, "Revision {} successfully restored", payload.revision);
+    Json(CreateOrUpdateWikiResponse::new(
+        true,
+        None,
+        Some(format!("/wikis/{}", &payload.username)),
+    ))
+}
+
+#[instrument(skip(state))]
+async fn list_wiki_revisions(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    token: AuthToken,
+    Query(params): Query<ReadParams>,
+) -> Json<ListRevisionsResponse> {
+    let authorized = authorize_read(
+        state.store.as_ref(),
+        &username,
+        &token,
+        params.share.as_deref(),
+    )
+    .await;
+    let revisions = match authorized {
+        Ok(()) => state
+            .store
+            .list_revisions(&username)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    match revisions {
+        Ok(revisions) => {
+            info!(event = "ListRevisions", data_id = %username, "Revisions successfully retrieved");
+            Json(ListRevisionsResponse {
+                success: true,
+                error: None,
+                revisions: revisions
+                    .into_iter()
+                    .map(|r| RevisionSummary {
+                        url: format!("/wikis/{}/revisions/{}", &username, r.id),
+                        id: r.id,
+                        page: r.page,
+                        created_at: r.created_at,
+                        summary: r.summary,
+                        author: r.author,
+                    })
+                    .collect(),
+            })
+        }
+        Err(e) => {
+            error!(event = "ListRevisions", data_id = %username, "{}", e);
+            Json(ListRevisionsResponse {
+                success: false,
+                error: Some(e),
+                revisions: vec![],
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+async fn get_wiki_revision(
+    State(state): State<AppState>,
+    Path((username, revision_id)): Path<(String, i64)>,
+    token: AuthToken,
+    Query(params): Query<ReadParams>,
+) -> Html<String> {
+    if let Err(e) = authorize_read(
+        state.store.as_ref(),
+        &username,
+        &token,
+        params.share.as_deref(),
+    )
+    .await
+    {
+        error!(event = "GetRevision", data_id = %username, "{}", e);
+        return Html(escape_html(&e));
+    }
+    match state.store.get_revision(&username, revision_id).await {
+        Ok(Some(revision)) => {
+            let author = revision
+                .author
+                .as_deref()
+                .map(|a| format!(" by {}", a))
+                .unwrap_or_default();
+            let banner = format!(
+                "<p><em>You are viewing revision {}{} of this wiki. <a href=\"{}\">Go to the current version</a>.</em></p>",
+                revision.id,
+                author,
+                page_url(&username, &revision.page)
+            );
+            let html = render_for(state.store.as_ref(), &username, &revision.markdown)
+                .await
+                .unwrap_or_else(|e| e);
+            let html = mark_missing_links(state.store.as_ref(), &html, &username, &token).await;
+            let html = carry_share(
+                &format!("{}\n{}", banner, html),
+                &username,
+                params.share.as_deref(),
+            );
+            let styled_content = style_html(&html, &username);
+            info!(event = "GetRevision", data_id = %username, "Revision {} successfully retrieved", revision_id);
+            Html(styled_content)
+        }
+        Ok(None) => {
+            error!(event = "GetRevision", data_id = %username, "Revision {} not found", revision_id);
+            Html(format!(
+                "Revision {} of the wiki for user {} not found...",
+                revision_id,
+                escape_html(&username)
+            ))
+        }
+        Err(e) => {
+            error!(event = "GetRevision", data_id = %username, "{}", e);
+            Html(format!(
+                "Revision {} of the wiki for user {} could not be loaded... Please try again later!",
+                revision_id,
+                escape_html(&username)
+            ))
+        }
+    }
+}
+
+#[instrument(skip(state))]
+async fn get_wiki_source(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<WikiSourceRequest>,
+) -> Json<WikiSourceResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match get_source(
+        state.store.as_ref(),
+        &payload.username,
+        payload.account.as_deref(),
+        credential.as_ref(),
+    )
+    .await
+    {
+        Ok(content) => {
+            info!(event = "GetWikiSource", data_id = %payload.username, "Wiki source successfully retrieved");
+            Json(WikiSourceResponse {
+                success: true,
+                error: None,
+                content: Some(content),
+            })
+        }
+        Err(e) => {
+            error!(event = "GetWikiSource", data_id = %payload.username, "{}", e);
+            Json(WikiSourceResponse {
+                success: false,
+                error: Some(e),
+                content: None,
+            })
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt().pretty().init();
 
+    // `personal-wiki migrate` only brings the database schema up to date
+    // `personal-wiki backfill-markdown` recovers Markdown for wikis stored as HTML only
+    // `personal-wiki duplicates [--resolve]` reports (and removes) duplicate usernames
+    match std::env::args().nth(1).as_deref() {
+        Some("migrate") => {
+            let config = DatabaseConfig::from_env()
+                .expect("Should be able to read the database configuration.");
+            LibsqlStore::open(&config)
+                .await
+                .expect("Should be able to apply the database migrations.");
+            tracing::info!("database schema is up to date");
+            return;
+        }
+        Some("backfill-markdown") => {
+            let config = DatabaseConfig::from_env()
+                .expect("Should be able to read the database configuration.");
+            let store = LibsqlStore::open(&config)
+                .await
+                .expect("Should be able to open the database.");
+            let count = store
+                .backfill_markdown(html2md::parse_html)
+                .await
+                .expect("Should be able to backfill the Markdown sources.");
+            tracing::info!("backfilled the Markdown source of {} wikis", count);
+            return;
+        }
+        Some("duplicates") => {
+            let config = DatabaseConfig::from_env()
+                .expect("Should be able to read the database configuration.");
+            let conn = config
+                .connect()
+                .await
+                .expect("Should be able to open the database.");
+            let duplicates = find_duplicate_users(&conn)
+                .await
+                .expect("Should be able to look for duplicate usernames.");
+            for duplicate in &duplicates {
+                tracing::info!(
+                    "username {} is shared by rows {:?}, row {} is the one being served",
+                    duplicate.user,
+                    duplicate.ids,
+                    duplicate.ids[0]
+                );
+            }
+            if std::env::args().nth(2).as_deref() == Some("--resolve") {
+                let deleted = resolve_duplicate_users(&conn)
+                    .await
+                    .expect("Should be able to resolve duplicate usernames.");
+                tracing::info!("deleted {} duplicate rows", deleted);
+            } else {
+                tracing::info!(
+                    "found {} duplicate usernames, run `personal-wiki duplicates --resolve` to keep only the rows being served",
+                    duplicates.len()
+                );
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    // storage configuration
+    let state = AppState::from_env()
+        .await
+        .expect("Should be able to open the configured storage backend.");
+
     // static assets
     let index_html = ServeFile::new("./pages/index.html");
     let about_html = ServeFile::new("./pages/about.html");
@@ -467,7 +985,7 @@ async fn main() {
             Method::PATCH,
             Method::DELETE,
         ])
-        .allow_headers(vec![CONTENT_TYPE]);
+        .allow_headers(vec![CONTENT_TYPE, AUTHORIZATION]);
     let governor_conf = Box::new(
         GovernorConfigBuilder::default()
             .per_second(60)
@@ -495,18 +1013,65 @@ async fn main() {
             "/wikis",
             post(create_wiki).patch(update_wiki).delete(delete_wiki),
         )
+        .route("/login", post(auth::login_handler))
+        .route("/logout", post(auth::logout_handler))
+        .route("/wikis/password", post(auth::change_password_handler))
+        .route("/wikis/audit", post(auth::audit_log_handler))
+        .route("/wikis/recovery/redeem", post(recovery::redeem_code))
+        .route(
+            "/wikis/recovery/regenerate",
+            post(recovery::regenerate_codes),
+        )
+        .route("/wikis/recovery/remaining", post(recovery::remaining_codes))
+        .route("/wikis/totp/enroll", post(totp::enroll))
+        .route("/wikis/totp/confirm", post(totp::confirm))
+        .route("/wikis/totp/disable", post(totp::disable_totp))
+        .route("/wikis/source", post(get_wiki_source))
+        .route(
+            "/wikis/keys",
+            post(keys::create_key).delete(keys::revoke_key),
+        )
+        .route("/wikis/keys/list", post(keys::list_keys))
+        .route("/wikis/restore", post(restore_wiki))
+        .route("/wikis/visibility", post(visibility::set_visibility))
+        .route("/wikis/settings", post(render::set_render_settings))
+        .route(
+            "/wikis/shares",
+            post(visibility::create_share).delete(visibility::revoke_share),
+        )
+        .route("/wikis/shares/list", post(visibility::list_shares))
+        .route(
+            "/wikis/collaborators",
+            post(collaborators::grant).delete(collaborators::revoke),
+        )
+        .route("/wikis/collaborators/list", post(collaborators::list))
+        .route(
+            "/wikis/pages",
+            post(pages::create_page)
+                .patch(pages::update_page)
+                .delete(pages::delete_page),
+        )
         .layer(governor_layer)
         .layer(cors_layer);
 
     // public routes
     let public_routes = Router::new()
+        .route("/search", get(search::search_page))
+        .route("/api/search", get(search::search_json))
         .route("/wikis/{username}", get(get_wiki))
+        .route("/wikis/{username}/revisions", get(list_wiki_revisions))
+        .route(
+            "/wikis/{username}/revisions/{revision_id}",
+            get(get_wiki_revision),
+        )
+        .route("/wikis/{username}/pages", get(pages::get_page_index))
+        .route("/wikis/{username}/{*path}", get(pages::get_page))
         .nest_service("/scripts", scripts)
         .route_service("/", index_html)
         .route_service("/about", about_html);
 
     // comhine in one router
-    let app = protected_routes.merge(public_routes);
+    let app = protected_routes.merge(public_routes).with_state(state);
 
     // start router
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
@@ -523,6 +1088,7 @@ async fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use hashing::{hash_password, verify_password};
 
     #[test]
     fn test_style_html() {
@@ -535,12 +1101,21 @@ mod tests {
     }
 
     #[test]
-    fn test_hash_password() {
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html("<a href=\"x\">Tom & Jerry's</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+    }
+
+    #[tokio::test]
+    #[allow(clippy::assertions_on_constants, clippy::to_string_in_format_args)]
+    async fn test_hash_password() {
         let password = "test_password";
-        let hashed_or_error = hash_pwd(password);
+        let hashed_or_error = hash_password(password).await;
         match hashed_or_error {
             Ok(s) => {
-                let verification = verify_hashed_pwd(password, &s);
+                let verification = verify_password(password, &s).await;
                 match verification {
                     Ok(is_match) => {
                         assert!(is_match);
@@ -560,72 +1135,251 @@ mod tests {
 
     #[tokio::test]
     async fn test_crud_operations() {
-        if std::env::var("LIBSQL_CONNECTION_STRING").is_err()
-            || std::env::var("LIBSQL_AUTH_TOKEN").is_err()
-        {
-            eprintln!("Skipping test because the necessary env variables are not set");
-            return;
-        } else {
-            let password = "test_password";
-            let hashed_or_error = hash_pwd(password);
-            let hashed: String;
-            match hashed_or_error {
-                Ok(s) => {
-                    hashed = s;
-                }
-                Err(e) => {
-                    eprintln!("An error occurred: {}", e.to_string());
-                    return;
-                }
-            }
-            // create record
-            let retval = insert_record("# hello", "test_user", &hashed).await;
-            match retval {
-                Some(s) => {
-                    eprintln!("An error occurred while inserting the record: {}", s);
-                    assert!(false);
-                }
-                None => {}
-            }
-            // get the record that has just been uploaded
-            let record = get_record("test_user").await;
-            match record {
-                Some(w) => {
-                    assert_eq!(w.content, "<h1>hello</h1>");
-                    assert_eq!(hashed, w.password);
-                }
-                None => {
-                    eprintln!("No row returned even if record should be present");
-                    assert!(false);
-                }
-            }
-            // update the record to a new one
-            let updatedval = update_record("# hi!", "test_user", "test_password").await;
-            match updatedval {
-                Some(s) => {
-                    eprintln!("An error occurred while updating the record: {}", s);
-                }
-                None => {}
-            }
-            let updated_record = get_record("test_user").await;
-            match updated_record {
-                Some(w) => {
-                    assert_eq!(w.content, "<h1>hi!</h1>");
-                    assert_eq!(hashed, w.password);
-                }
-                None => {
-                    eprintln!("No row returned even if record should be present");
-                    assert!(false);
-                }
-            }
-            // delete record
-            let delval = delete_record("test_user", "test_password").await;
-            match delval {
-                Some(s) => {
-                    eprintln!("An error occurred while deleting the record: {}", s);
-                }
-                None => {}
-            }
-        }
+        let config = DatabaseConfig::Local {
+            path: ":memory:".to_string(),
+        };
+        let store = LibsqlStore::open(&config)
+            .await
+            .expect("Should be able to open an in-memory database");
+        let password = "test_password";
+        let hashed = hash_password(password)
+            .await
+            .expect("Should be able to hash the password");
+        // create record
+        let retval = insert_record(&store, "# hello", "test_user", &hashed).await;
+        assert_eq!(retval, None);
+        // get the record that has just been uploaded
+        let record = store
+            .get_wiki("test_user")
+            .await
+            .ok()
+            .flatten()
+            .expect("Record should be present after insertion");
+        assert_eq!(record.content, "<h1 id=\"user-content-hello\">hello<a class=\"anchor\" href=\"#user-content-hello\" aria-label=\"Permalink to this section\">#</a></h1>");
+        assert_eq!(record.markdown, Some("# hello".to_string()));
+        assert_eq!(hashed, record.password);
+        // creating the same user twice is rejected
+        let retval = insert_record(&store, "# hello", "test_user", &hashed).await;
+        assert_eq!(retval, Some("Username is already taken".to_string()));
+        // update the record to a new one
+        let wrong = Credential::Password("wrong_password".to_string());
+        let updatedval =
+            update_record(&store, "# hi!", "test_user", None, Some(&wrong), None).await;
+        assert_eq!(updatedval, Some("Wrong username or password".to_string()));
+        let right = Credential::Password(password.to_string());
+        let updatedval =
+            update_record(&store, "# hi!", "test_user", None, Some(&right), None).await;
+        assert_eq!(updatedval, None);
+        let updated_record = store
+            .get_wiki("test_user")
+            .await
+            .ok()
+            .flatten()
+            .expect("Record should be present after update");
+        assert_eq!(updated_record.content, "<h1 id=\"user-content-hi\">hi!<a class=\"anchor\" href=\"#user-content-hi\" aria-label=\"Permalink to this section\">#</a></h1>");
+        assert_eq!(hashed, updated_record.password);
+        // delete record
+        let delval = delete_record(&store, "test_user", Some(&right)).await;
+        assert_eq!(delval, None);
+        assert_eq!(store.get_wiki("test_user").await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_creation() {
+        let config = DatabaseConfig::Local {
+            path: ":memory:".to_string(),
+        };
+        let store = LibsqlStore::open(&config)
+            .await
+            .expect("Should be able to open an in-memory database");
+        let (first, second) = tokio::join!(
+            insert_record(&store, "# first", "test_user", "hash1"),
+            insert_record(&store, "# second", "test_user", "hash2"),
+        );
+        let mut outcomes = vec![first, second];
+        outcomes.sort();
+        assert_eq!(
+            outcomes,
+            vec![None, Some("Username is already taken".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wiki_handlers() {
+        let state = AppState::new(Arc::new(MemoryStore::new()));
+        let request = |content: &str, password: &str| {
+            Json(CreateOrUpdateWikiRequest {
+                content: content.to_string(),
+                username: "test_user".to_string(),
+                password: Some(password.to_string()),
+                account: None,
+                summary: None,
+            })
+        };
+        let created = create_wiki(State(state.clone()), request("# hello", "test_password")).await;
+        assert!(created.success);
+        assert_eq!(created.url, Some("/wikis/test_user".to_string()));
+        // usernames are validated, with a structured error
+        let refused = create_wiki(
+            State(state.clone()),
+            Json(CreateOrUpdateWikiRequest {
+                content: "# hello".to_string(),
+                username: "Test_User".to_string(),
+                password: Some("test_password".to_string()),
+                account: None,
+                summary: None,
+            }),
+        )
+        .await;
+        assert!(!refused.success);
+        assert_eq!(refused.error, Some("Username is already taken".to_string()));
+        assert_eq!(
+            refused.0.validation.map(|v| (v.field, v.reason)),
+            Some(("username", UsernameError::Taken))
+        );
+        assert_eq!(
+            created.recovery_codes.as_ref().map(Vec::len),
+            Some(recovery::RECOVERY_CODE_COUNT)
+        );
+        let page = get_wiki(
+            State(state.clone()),
+            Path("test_user".to_string()),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(page.0.contains("<h1 id=\"user-content-hello\">hello"));
+        // the username of the URL is escaped
+        let page = get_wiki(
+            State(state.clone()),
+            Path("<script>".to_string()),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(page.0.contains("user &lt;script&gt; not found"));
+        let updated = update_wiki(
+            State(state.clone()),
+            AuthToken(None),
+            request("# hi!", "wrong_password"),
+        )
+        .await;
+        assert!(!updated.success);
+        let updated = update_wiki(
+            State(state.clone()),
+            AuthToken(None),
+            request("# hi!", "test_password"),
+        )
+        .await;
+        assert!(updated.success);
+        let page = get_wiki(
+            State(state.clone()),
+            Path("test_user".to_string()),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(page.0.contains("<h1 id=\"user-content-hi\">hi!"));
+        let source = |password: &str| {
+            Json(WikiSourceRequest {
+                username: "test_user".to_string(),
+                password: Some(password.to_string()),
+                account: None,
+            })
+        };
+        let fetched = get_wiki_source(
+            State(state.clone()),
+            AuthToken(None),
+            source("wrong_password"),
+        )
+        .await;
+        assert!(!fetched.success);
+        assert_eq!(fetched.content, None);
+        let fetched = get_wiki_source(
+            State(state.clone()),
+            AuthToken(None),
+            source("test_password"),
+        )
+        .await;
+        assert!(fetched.success);
+        assert_eq!(fetched.content, Some("# hi!".to_string()));
+        // restore the first revision as the new head
+        let revisions = list_wiki_revisions(
+            State(state.clone()),
+            Path("test_user".to_string()),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(revisions.success);
+        assert_eq!(revisions.revisions.len(), 2);
+        let first = revisions.revisions[1].id;
+        let page = get_wiki_revision(
+            State(state.clone()),
+            Path(("test_user".to_string(), first)),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(page.0.contains("<h1 id=\"user-content-hello\">hello"));
+        // the username of the URL is escaped
+        let page = get_wiki_revision(
+            State(state.clone()),
+            Path(("<script>".to_string(), first)),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(page.0.contains("user &lt;script&gt; not found"));
+        let restored = restore_wiki(
+            State(state.clone()),
+            AuthToken(None),
+            Json(RestoreRevisionRequest {
+                username: "test_user".to_string(),
+                password: Some("test_password".to_string()),
+                account: None,
+                revision: first,
+            }),
+        )
+        .await;
+        assert!(restored.success);
+        let page = get_wiki(
+            State(state.clone()),
+            Path("test_user".to_string()),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(page.0.contains("<h1 id=\"user-content-hello\">hello"));
+        let revisions = list_wiki_revisions(
+            State(state.clone()),
+            Path("test_user".to_string()),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert_eq!(revisions.revisions.len(), 3);
+        assert_eq!(
+            revisions.revisions[0].summary,
+            Some(format!("Restored revision {}", first))
+        );
+        let deleted = delete_wiki(
+            State(state.clone()),
+            AuthToken(None),
+            Json(DeleteWikiRequest {
+                username: "test_user".to_string(),
+                password: Some("test_password".to_string()),
+            }),
+        )
+        .await;
+        assert!(deleted.success);
+        let page = get_wiki(
+            State(state),
+            Path("test_user".to_string()),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(page.0.contains("not found"));
     }
 }
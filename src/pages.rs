@@ -0,0 +1,775 @@
+//! Pages of a wiki other than its home page, served at `/wikis/{username}/{path}`.
+
+use crate::auth::{authorize, AuthToken, Credential, Scope};
+use crate::render::render_for;
+use crate::store::{Page, StoreError, WikiStore};
+use crate::visibility::{authorize_read, carry_share, check_read_access, ReadParams};
+use crate::wikilinks::mark_missing_links;
+use crate::{escape_html, style_html, AppState, CreateOrUpdateWikiResponse, DeleteWikiResponse};
+use axum::{
+    extract::{Path, Query, State},
+    response::{Html, Json},
+};
+use derivative::Derivative;
+use serde::Deserialize;
+use tracing::{error, info, instrument};
+
+/// First path segments used by other routes under `/wikis/{username}/`.
+const RESERVED_PAGE_PATHS: &[&str] = &["pages", "revisions"];
+const MAX_PAGE_DEPTH: usize = 8;
+const MAX_SEGMENT_LENGTH: usize = 64;
+
+/// Turns a user supplied page path into its canonical slug form: segments are
+/// trimmed, lowercased and have their spaces replaced by dashes, so that
+/// `Guides/Getting Started` becomes `guides/getting-started`.
+pub fn normalize_page_path(path: &str) -> Result<String, String> {
+    let trimmed = path.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        return Err("Page path cannot be empty".to_string());
+    }
+    let mut segments = Vec::new();
+    for segment in trimmed.split('/') {
+        let slug = segment
+            .trim()
+            .to_lowercase()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join("-");
+        if slug.is_empty() {
+            return Err("Page path cannot contain empty segments".to_string());
+        }
+        if slug.len() > MAX_SEGMENT_LENGTH {
+            return Err(format!(
+                "Page path segments cannot be longer than {} characters",
+                MAX_SEGMENT_LENGTH
+            ));
+        }
+        if !slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(
+                "Page paths can only contain letters, digits, spaces, '-' and '_'".to_string(),
+            );
+        }
+        segments.push(slug);
+    }
+    if segments.len() > MAX_PAGE_DEPTH {
+        return Err(format!(
+            "Page paths cannot be nested more than {} levels deep",
+            MAX_PAGE_DEPTH
+        ));
+    }
+    if RESERVED_PAGE_PATHS.contains(&segments[0].as_str()) {
+        return Err(format!("'{}' is a reserved page name", segments[0]));
+    }
+    Ok(segments.join("/"))
+}
+
+fn render_page_index(username: &str, pages: &[Page]) -> String {
+    let username = escape_html(username);
+    let mut html = format!(
+        "<h1>Pages</h1>\n<ul>\n<li><a href=\"/wikis/{}\">Home</a></li>\n",
+        username
+    );
+    for page in pages {
+        html.push_str(&format!(
+            "<li><a href=\"/wikis/{}/{}\">{}</a></li>\n",
+            username,
+            escape_html(&page.path),
+            escape_html(&page.path)
+        ));
+    }
+    html.push_str("</ul>");
+    html
+}
+
+fn render_breadcrumbs(username: &str, path: &str) -> String {
+    let username = escape_html(username);
+    let mut html = format!("<p><a href=\"/wikis/{}\">{}</a>", username, username);
+    let mut prefix = String::new();
+    for segment in path.split('/') {
+        if !prefix.is_empty() {
+            prefix.push('/');
+        }
+        prefix.push_str(segment);
+        html.push_str(&format!(
+            " / <a href=\"/wikis/{}/{}\">{}</a>",
+            username,
+            escape_html(&prefix),
+            escape_html(segment)
+        ));
+    }
+    html.push_str(&format!(
+        " · <a href=\"/wikis/{}/pages\">All pages</a></p>",
+        username
+    ));
+    html
+}
+
+/// Form to create a missing page, shown to those who may write to the wiki,
+/// e.g. after following a link to the page.
+fn render_create_form(username: &str, path: &str) -> String {
+    format!(
+        "<h1>Create {path}</h1>\n<p>This page does not exist yet. Write it below to create it.</p>\n<textarea id=\"pageContent\" class=\"textarea textarea-bordered w-full h-64 mt-4 font-mono\" placeholder=\"# {path}\"></textarea>\n<input id=\"pageSummary\" type=\"text\" class=\"input input-bordered w-full mt-4\" placeholder=\"Edit summary (optional)\" />\n<button id=\"createPage\" class=\"btn btn-primary mt-4\" data-username=\"{username}\" data-path=\"{path}\">Create Page</button>\n<p id=\"createStatus\" class=\"mt-4\"></p>\n<script src=\"/scripts/create-page.js\"></script>",
+        username = escape_html(username),
+        path = path
+    )
+}
+
+pub async fn insert_page_record(
+    store: &dyn WikiStore,
+    username: &str,
+    account: Option<&str>,
+    credential: Option<&Credential>,
+    path: &str,
+    markdown_text: &str,
+    summary: Option<&str>,
+) -> Option<String> {
+    let author = match authorize(store, username, account, credential, Scope::Write).await {
+        Ok(author) => author,
+        Err(e) => return Some(e),
+    };
+    let html_text = match render_for(store, username, markdown_text).await {
+        Ok(html) => html,
+        Err(e) => return Some(e),
+    };
+    match store
+        .insert_page(username, path, markdown_text, &html_text, summary, &author)
+        .await
+    {
+        Ok(()) => None,
+        Err(StoreError::AlreadyExists) => Some("Page already exists".to_string()),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+pub async fn update_page_record(
+    store: &dyn WikiStore,
+    username: &str,
+    account: Option<&str>,
+    credential: Option<&Credential>,
+    path: &str,
+    markdown_text: &str,
+    summary: Option<&str>,
+) -> Option<String> {
+    let author = match authorize(store, username, account, credential, Scope::Write).await {
+        Ok(author) => author,
+        Err(e) => return Some(e),
+    };
+    let html_text = match render_for(store, username, markdown_text).await {
+        Ok(html) => html,
+        Err(e) => return Some(e),
+    };
+    match store
+        .update_page(username, path, markdown_text, &html_text, summary, &author)
+        .await
+    {
+        Ok(()) => None,
+        Err(StoreError::NotFound) => Some("Page does not exist".to_string()),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+pub async fn delete_page_record(
+    store: &dyn WikiStore,
+    username: &str,
+    account: Option<&str>,
+    credential: Option<&Credential>,
+    path: &str,
+) -> Option<String> {
+    if let Err(e) = authorize(store, username, account, credential, Scope::Delete).await {
+        return Some(e);
+    }
+    match store.delete_page(username, path).await {
+        Ok(()) => None,
+        Err(StoreError::NotFound) => Some("Page does not exist".to_string()),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct CreateOrUpdatePageRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Account to sign in as with a password when editing someone else's
+    /// wiki as a collaborator, the owner by default.
+    #[serde(default)]
+    pub account: Option<String>,
+    pub path: String,
+    pub content: String,
+    /// Optional edit summary stored with the revision.
+    #[serde(default)]
+    pub summary: Option<String>,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct DeletePageRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Account to sign in as with a password when editing someone else's
+    /// wiki as a collaborator, the owner by default.
+    #[serde(default)]
+    pub account: Option<String>,
+    pub path: String,
+}
+
+#[instrument(skip(state))]
+pub async fn create_page(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<CreateOrUpdatePageRequest>,
+) -> Json<CreateOrUpdateWikiResponse> {
+    let path = match normalize_page_path(&payload.path) {
+        Ok(p) => p,
+        Err(e) => return Json(CreateOrUpdateWikiResponse::new(false, Some(e), None)),
+    };
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    if let Some(error_msg) = insert_page_record(
+        state.store.as_ref(),
+        &payload.username,
+        payload.account.as_deref(),
+        credential.as_ref(),
+        &path,
+        &payload.content,
+        payload.summary.as_deref(),
+    )
+    .await
+    {
+        error!(event = "CreatePage", data_id = %payload.username, "{}", error_msg);
+        return Json(CreateOrUpdateWikiResponse::new(
+            false,
+            Some(error_msg),
+            None,
+        ));
+    }
+    info!(event = "CreatePage", data_id = %payload.username, "Page {} successfully created", path);
+    Json(CreateOrUpdateWikiResponse::new(
+        true,
+        None,
+        Some(format!("/wikis/{}/{}", &payload.username, path)),
+    ))
+}
+
+#[instrument(skip(state))]
+pub async fn update_page(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<CreateOrUpdatePageRequest>,
+) -> Json<CreateOrUpdateWikiResponse> {
+    let path = match normalize_page_path(&payload.path) {
+        Ok(p) => p,
+        Err(e) => return Json(CreateOrUpdateWikiResponse::new(false, Some(e), None)),
+    };
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    if let Some(error_msg) = update_page_record(
+        state.store.as_ref(),
+        &payload.username,
+        payload.account.as_deref(),
+        credential.as_ref(),
+        &path,
+        &payload.content,
+        payload.summary.as_deref(),
+    )
+    .await
+    {
+        error!(event = "UpdatePage", data_id = %payload.username, "{}", error_msg);
+        return Json(CreateOrUpdateWikiResponse::new(
+            false,
+            Some(error_msg),
+            None,
+        ));
+    }
+    info!(event = "UpdatePage", data_id = %payload.username, "Page {} successfully updated", path);
+    Json(CreateOrUpdateWikiResponse::new(
+        true,
+        None,
+        Some(format!("/wikis/{}/{}", &payload.username, path)),
+    ))
+}
+
+#[instrument(skip(state))]
+pub async fn delete_page(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<DeletePageRequest>,
+) -> Json<DeleteWikiResponse> {
+    let path = match normalize_page_path(&payload.path) {
+        Ok(p) => p,
+        Err(e) => {
+            return Json(DeleteWikiResponse {
+                success: false,
+                error: Some(e),
+            })
+        }
+    };
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match delete_page_record(
+        state.store.as_ref(),
+        &payload.username,
+        payload.account.as_deref(),
+        credential.as_ref(),
+        &path,
+    )
+    .await
+    {
+        Some(s) => {
+            error!(event = "DeletePage", data_id = %payload.username, "{}", s);
+            Json(DeleteWikiResponse {
+                success: false,
+                error: Some(s),
+            })
+        }
+        None => {
+            info!(event = "DeletePage", data_id = %payload.username, "Page {} successfully deleted", path);
+            Json(DeleteWikiResponse {
+                success: true,
+                error: None,
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn get_page(
+    State(state): State<AppState>,
+    Path((username, path)): Path<(String, String)>,
+    token: AuthToken,
+    Query(params): Query<ReadParams>,
+) -> Html<String> {
+    let path = match normalize_page_path(&path) {
+        Ok(p) => p,
+        Err(e) => return Html(escape_html(&e)),
+    };
+    if let Err(e) = authorize_read(
+        state.store.as_ref(),
+        &username,
+        &token,
+        params.share.as_deref(),
+    )
+    .await
+    {
+        error!(event = "GetPage", data_id = %username, "{}", e);
+        return Html(escape_html(&e));
+    }
+    match state.store.get_page(&username, &path).await {
+        Ok(Some(page)) => {
+            let content =
+                mark_missing_links(state.store.as_ref(), &page.content, &username, &token).await;
+            let html = format!("{}\n{}", render_breadcrumbs(&username, &path), content);
+            let html = carry_share(&html, &username, params.share.as_deref());
+            info!(event = "GetPage", data_id = %username, "Page {} successfully retrieved", path);
+            Html(style_html(&html, &username))
+        }
+        Ok(None) => {
+            error!(event = "GetPage", data_id = %username, "Page {} not found", path);
+            let credential = Credential::resolve(None, &token);
+            if authorize(
+                state.store.as_ref(),
+                &username,
+                None,
+                credential.as_ref(),
+                Scope::Write,
+            )
+            .await
+            .is_ok()
+            {
+                let html = format!(
+                    "{}\n{}",
+                    render_breadcrumbs(&username, &path),
+                    render_create_form(&username, &path)
+                );
+                return Html(style_html(&html, &username));
+            }
+            Html(format!(
+                "Page {} of the wiki for user {} not found... Please create it and try again!",
+                escape_html(&path),
+                escape_html(&username)
+            ))
+        }
+        Err(e) => {
+            error!(event = "GetPage", data_id = %username, "{}", e);
+            Html(format!(
+                "Page {} of the wiki for user {} could not be loaded... Please try again later!",
+                escape_html(&path),
+                escape_html(&username)
+            ))
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn get_page_index(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    token: AuthToken,
+    Query(params): Query<ReadParams>,
+) -> Html<String> {
+    match state.store.get_wiki(&username).await {
+        Ok(Some(wiki)) => {
+            if let Err(e) = check_read_access(
+                state.store.as_ref(),
+                &username,
+                wiki.visibility,
+                &token,
+                params.share.as_deref(),
+            )
+            .await
+            {
+                error!(event = "GetPageIndex", data_id = %username, "{}", e);
+                return Html(escape_html(&e));
+            }
+        }
+        Ok(None) => {
+            error!(event = "GetPageIndex", data_id = %username, "Wiki not found for user {}", username);
+            return Html(format!(
+                "Wiki for user {} not found... Please create one and try again!",
+                escape_html(&username)
+            ));
+        }
+        Err(e) => {
+            error!(event = "GetPageIndex", data_id = %username, "{}", e);
+            return Html(format!(
+                "Wiki for user {} could not be loaded... Please try again later!",
+                escape_html(&username)
+            ));
+        }
+    }
+    match state.store.list_pages(&username).await {
+        Ok(pages) => {
+            info!(event = "GetPageIndex", data_id = %username, "Page index successfully retrieved");
+            let html = carry_share(
+                &render_page_index(&username, &pages),
+                &username,
+                params.share.as_deref(),
+            );
+            Html(style_html(&html, &username))
+        }
+        Err(e) => {
+            error!(event = "GetPageIndex", data_id = %username, "{}", e);
+            Html(format!(
+                "Pages of the wiki for user {} could not be loaded... Please try again later!",
+                escape_html(&username)
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::login;
+    use crate::store::{MemoryStore, Visibility};
+    use crate::visibility::mint_share_link;
+    use crate::{create_wiki, restore_wiki, CreateOrUpdateWikiRequest, RestoreRevisionRequest};
+    use std::sync::Arc;
+
+    fn page_request(path: &str, content: &str) -> Json<CreateOrUpdatePageRequest> {
+        Json(CreateOrUpdatePageRequest {
+            username: "test_user".to_string(),
+            password: None,
+            account: None,
+            path: path.to_string(),
+            content: content.to_string(),
+            summary: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_page_handlers() {
+        let state = AppState::new(Arc::new(MemoryStore::new()));
+        let created = create_wiki(
+            State(state.clone()),
+            Json(CreateOrUpdateWikiRequest {
+                content: "# home".to_string(),
+                username: "test_user".to_string(),
+                password: Some("test_password".to_string()),
+                account: None,
+                summary: None,
+            }),
+        )
+        .await;
+        assert!(created.success);
+        // page edits below authenticate with a session instead of the password
+        let (token, _) = login(state.store.as_ref(), "test_user", "test_password", None)
+            .await
+            .unwrap();
+        let session = || AuthToken(Some(token.clone()));
+        let unauthenticated = create_page(
+            State(state.clone()),
+            AuthToken(None),
+            page_request("guides/setup", "# setup"),
+        )
+        .await;
+        assert!(!unauthenticated.success);
+        let created = create_page(
+            State(state.clone()),
+            session(),
+            page_request("Guides/Setup", "# setup"),
+        )
+        .await;
+        assert!(created.success);
+        assert_eq!(
+            created.url,
+            Some("/wikis/test_user/guides/setup".to_string())
+        );
+        let duplicate = create_page(
+            State(state.clone()),
+            session(),
+            page_request("guides/setup", "# x"),
+        )
+        .await;
+        assert_eq!(duplicate.error, Some("Page already exists".to_string()));
+        let updated = update_page(
+            State(state.clone()),
+            session(),
+            page_request("guides/setup", "# set up"),
+        )
+        .await;
+        assert!(updated.success);
+        let page_path = || Path(("test_user".to_string(), "guides/setup".to_string()));
+        let page = get_page(
+            State(state.clone()),
+            page_path(),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(page.0.contains("<h1 id=\"user-content-set-up\">set up"));
+        let index = get_page_index(
+            State(state.clone()),
+            Path("test_user".to_string()),
+            session(),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(index.0.contains("/wikis/test_user/guides/setup"));
+        // pages of a private wiki are only served to its owner
+        state
+            .store
+            .set_visibility("test_user", Visibility::Private)
+            .await
+            .unwrap();
+        let hidden = get_page(
+            State(state.clone()),
+            page_path(),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(!hidden.0.contains("<h1 id=\"user-content-set-up\">set up"));
+        let page = get_page(
+            State(state.clone()),
+            page_path(),
+            session(),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(page.0.contains("<h1 id=\"user-content-set-up\">set up"));
+        // readers with a share link keep it on the links to other pages
+        let password = Credential::Password("test_password".to_string());
+        let (_, secret) =
+            mint_share_link(state.store.as_ref(), "test_user", Some(&password), "team")
+                .await
+                .unwrap();
+        let shared = || {
+            Query(ReadParams {
+                share: Some(secret.clone()),
+            })
+        };
+        let page = get_page(State(state.clone()), page_path(), AuthToken(None), shared()).await;
+        assert!(page.0.contains("<h1 id=\"user-content-set-up\">set up"));
+        assert!(page.0.contains(&format!(
+            "<a href=\"/wikis/test_user/pages?share={}\">All pages</a>",
+            secret
+        )));
+        let index = get_page_index(
+            State(state.clone()),
+            Path("test_user".to_string()),
+            AuthToken(None),
+            shared(),
+        )
+        .await;
+        assert!(index.0.contains(&format!(
+            "<a href=\"/wikis/test_user/guides/setup?share={}\">",
+            secret
+        )));
+        let deleted = delete_page(
+            State(state.clone()),
+            session(),
+            Json(DeletePageRequest {
+                username: "test_user".to_string(),
+                password: None,
+                account: None,
+                path: "guides/setup".to_string(),
+            }),
+        )
+        .await;
+        assert!(deleted.success);
+        // a deleted page can be brought back from its history
+        let revision = state
+            .store
+            .list_revisions("test_user")
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|r| r.page == "guides/setup")
+            .unwrap();
+        let restored = restore_wiki(
+            State(state.clone()),
+            AuthToken(None),
+            Json(RestoreRevisionRequest {
+                username: "test_user".to_string(),
+                password: Some("test_password".to_string()),
+                account: None,
+                revision: revision.id,
+            }),
+        )
+        .await;
+        assert!(restored.success);
+        let page = get_page(
+            State(state),
+            page_path(),
+            session(),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(page.0.contains("<h1 id=\"user-content-set-up\">set up"));
+    }
+
+    #[tokio::test]
+    async fn test_wiki_links_to_missing_pages() {
+        let state = AppState::new(Arc::new(MemoryStore::new()));
+        let created = create_wiki(
+            State(state.clone()),
+            Json(CreateOrUpdateWikiRequest {
+                content: "# home".to_string(),
+                username: "test_user".to_string(),
+                password: Some("test_password".to_string()),
+                account: None,
+                summary: None,
+            }),
+        )
+        .await;
+        assert!(created.success);
+        let (token, _) = login(state.store.as_ref(), "test_user", "test_password", None)
+            .await
+            .unwrap();
+        let session = || AuthToken(Some(token.clone()));
+        let created = create_page(
+            State(state.clone()),
+            session(),
+            page_request("guides/setup", "Next: [[Guides/Install|installing]]"),
+        )
+        .await;
+        assert!(created.success);
+        let setup = || Path(("test_user".to_string(), "guides/setup".to_string()));
+        let install = || Path(("test_user".to_string(), "guides/install".to_string()));
+        let page = get_page(
+            State(state.clone()),
+            setup(),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(page.0.contains("<a class=\"wikilink wikilink-missing\" title=\"Create this page\" href=\"/wikis/test_user/guides/install\">installing</a>"));
+        // following the link leads to a form for the owner only
+        let missing = get_page(
+            State(state.clone()),
+            install(),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(missing.0.contains("not found"));
+        assert!(!missing.0.contains("id=\"createPage\""));
+        // usernames of the URL are escaped
+        let missing = get_page(
+            State(state.clone()),
+            Path(("<script>".to_string(), "guides/install".to_string())),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(missing.0.contains("user &lt;script&gt; not found"));
+        let index = get_page_index(
+            State(state.clone()),
+            Path("<script>".to_string()),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(index.0.contains("user &lt;script&gt; not found"));
+        let missing = get_page(
+            State(state.clone()),
+            install(),
+            session(),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(missing.0.contains("id=\"createPage\""));
+        assert!(missing.0.contains("data-path=\"guides/install\""));
+        let created = create_page(
+            State(state.clone()),
+            session(),
+            page_request("guides/install", "# install"),
+        )
+        .await;
+        assert!(created.success);
+        let page = get_page(
+            State(state),
+            setup(),
+            AuthToken(None),
+            Query(ReadParams::default()),
+        )
+        .await;
+        assert!(page.0.contains(
+            "<a class=\"wikilink\" href=\"/wikis/test_user/guides/install\">installing</a>"
+        ));
+    }
+
+    #[test]
+    fn test_normalize_page_path() {
+        assert_eq!(
+            normalize_page_path("/Guides/Getting  Started/"),
+            Ok("guides/getting-started".to_string())
+        );
+        assert_eq!(normalize_page_path("notes"), Ok("notes".to_string()));
+        assert!(normalize_page_path("").is_err());
+        assert!(normalize_page_path("a//b").is_err());
+        assert!(normalize_page_path("../etc/passwd").is_err());
+        assert!(normalize_page_path("<script>").is_err());
+        assert!(normalize_page_path("revisions/1").is_err());
+        assert!(normalize_page_path("pages").is_err());
+        assert!(normalize_page_path(&"a/".repeat(MAX_PAGE_DEPTH + 1)).is_err());
+    }
+
+    #[test]
+    fn test_render_page_index() {
+        let pages = vec![Page {
+            path: "guides/setup".to_string(),
+            content: "<h1>setup</h1>".to_string(),
+            markdown: "# setup".to_string(),
+            updated_at: 0,
+        }];
+        let html = render_page_index("test_user", &pages);
+        assert!(html.contains("<a href=\"/wikis/test_user\">Home</a>"));
+        assert!(html.contains("<a href=\"/wikis/test_user/guides/setup\">guides/setup</a>"));
+        let html = render_page_index("<b>", &pages);
+        assert!(html.contains("<a href=\"/wikis/&lt;b&gt;\">Home</a>"));
+    }
+
+    #[test]
+    fn test_render_breadcrumbs() {
+        assert_eq!(
+            render_breadcrumbs("<b>", "guides/setup"),
+            "<p><a href=\"/wikis/&lt;b&gt;\">&lt;b&gt;</a> / <a href=\"/wikis/&lt;b&gt;/guides\">guides</a> / <a href=\"/wikis/&lt;b&gt;/guides/setup\">setup</a> · <a href=\"/wikis/&lt;b&gt;/pages\">All pages</a></p>"
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//! One-time recovery codes, to set a new password without the old one.
+//!
+//! A set of codes is handed out once when a wiki is created and can be
+//! regenerated by the owner. Each code works once.
+
+use crate::auth::{
+    authenticate_owner, check_throttle, hash_token, record_failure, reset_throttle, AuthToken,
+    Credential,
+};
+use crate::hashing::hash_password;
+use crate::store::{StoreError, WikiStore};
+use crate::{AppState, DeleteWikiResponse};
+use axum::{extract::State, response::Json};
+use derivative::Derivative;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, instrument};
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+/// Audit log event recorded when a recovery code is redeemed.
+pub const PASSWORD_RECOVERED: &str = "password_recovered";
+
+/// Generates a fresh set of codes such as `3f9a1-07bc2-d4e58-a6b03`, with 80
+/// bits of entropy each.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 10];
+            rand::fill(&mut bytes);
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            hex.as_bytes()
+                .chunks(5)
+                .map(|c| String::from_utf8_lossy(c).into_owned())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// Codes are compared without their dashes, whitespace or case, so that they
+/// can be typed back loosely.
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+pub fn hash_code(code: &str) -> String {
+    hash_token(&normalize_code(code))
+}
+
+/// Replaces the recovery codes of `username` with a new set and returns it.
+/// The codes are not stored and cannot be shown again.
+pub async fn issue_recovery_codes(
+    store: &dyn WikiStore,
+    username: &str,
+) -> Result<Vec<String>, String> {
+    let codes = generate_recovery_codes();
+    let hashes: Vec<String> = codes.iter().map(|c| hash_code(c)).collect();
+    store
+        .replace_recovery_codes(username, &hashes)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(codes)
+}
+
+pub async fn regenerate_recovery_codes(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+) -> Result<Vec<String>, String> {
+    authenticate_owner(store, username, credential).await?;
+    issue_recovery_codes(store, username).await
+}
+
+pub async fn remaining_recovery_codes(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+) -> Result<usize, String> {
+    authenticate_owner(store, username, credential).await?;
+    store
+        .count_recovery_codes(username)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Sets a new password with a recovery code, which is used up. Like a
+/// password change, every session and API key of the account is revoked.
+/// Wrong codes count towards the lockout of the account, like wrong passwords.
+pub async fn redeem_recovery_code(
+    store: &dyn WikiStore,
+    username: &str,
+    code: &str,
+    new_password: &str,
+) -> Option<String> {
+    if new_password.is_empty() {
+        return Some("The new password cannot be empty".to_string());
+    }
+    let throttled = match check_throttle(store, username).await {
+        Ok(t) => t,
+        Err(e) => return Some(e),
+    };
+    let code_hash = hash_code(code);
+    // wrong codes are turned down before the new password is hashed, so that
+    // guessing them costs no Argon2 work
+    let redeemed = match store.has_recovery_code(username, &code_hash).await {
+        Ok(false) => Err(StoreError::NotFound),
+        Ok(true) => match hash_password(new_password).await {
+            Ok(hashed) => {
+                store
+                    .redeem_recovery_code(username, &code_hash, &hashed, PASSWORD_RECOVERED)
+                    .await
+            }
+            Err(e) => return Some(e),
+        },
+        Err(e) => Err(e),
+    };
+    match redeemed {
+        Ok(()) if throttled => reset_throttle(store, username).await.err(),
+        Ok(()) => None,
+        Err(StoreError::NotFound) => Some(
+            record_failure(store, username)
+                .await
+                .err()
+                .unwrap_or_else(|| "Invalid or already used recovery code".to_string()),
+        ),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct RedeemRecoveryCodeRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    pub code: String,
+    #[derivative(Debug = "ignore")]
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct RecoveryCodesRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Derivative)]
+#[derivative(Debug)]
+pub struct RecoveryCodesResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    #[derivative(Debug = "ignore")]
+    pub codes: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RemainingRecoveryCodesResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    pub remaining: Option<usize>,
+}
+
+#[instrument(skip(state))]
+pub async fn redeem_code(
+    State(state): State<AppState>,
+    Json(payload): Json<RedeemRecoveryCodeRequest>,
+) -> Json<DeleteWikiResponse> {
+    match redeem_recovery_code(
+        state.store.as_ref(),
+        &payload.username,
+        &payload.code,
+        &payload.new_password,
+    )
+    .await
+    {
+        Some(e) => {
+            error!(event = "RedeemRecoveryCode", data_id = %payload.username, "{}", e);
+            Json(DeleteWikiResponse {
+                success: false,
+                error: Some(e),
+            })
+        }
+        None => {
+            info!(event = "RedeemRecoveryCode", data_id = %payload.username, "Password successfully recovered");
+            Json(DeleteWikiResponse {
+                success: true,
+                error: None,
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn regenerate_codes(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<RecoveryCodesRequest>,
+) -> Json<RecoveryCodesResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match regenerate_recovery_codes(state.store.as_ref(), &payload.username, credential.as_ref())
+        .await
+    {
+        Ok(codes) => {
+            info!(event = "RegenerateRecoveryCodes", data_id = %payload.username, "Recovery codes successfully regenerated");
+            Json(RecoveryCodesResponse {
+                success: true,
+                error: None,
+                codes: Some(codes),
+            })
+        }
+        Err(e) => {
+            error!(event = "RegenerateRecoveryCodes", data_id = %payload.username, "{}", e);
+            Json(RecoveryCodesResponse {
+                success: false,
+                error: Some(e),
+                codes: None,
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn remaining_codes(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<RecoveryCodesRequest>,
+) -> Json<RemainingRecoveryCodesResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match remaining_recovery_codes(state.store.as_ref(), &payload.username, credential.as_ref())
+        .await
+    {
+        Ok(remaining) => {
+            info!(event = "RemainingRecoveryCodes", data_id = %payload.username, "Recovery codes successfully counted");
+            Json(RemainingRecoveryCodesResponse {
+                success: true,
+                error: None,
+                remaining: Some(remaining),
+            })
+        }
+        Err(e) => {
+            error!(event = "RemainingRecoveryCodes", data_id = %payload.username, "{}", e);
+            Json(RemainingRecoveryCodesResponse {
+                success: false,
+                error: Some(e),
+                remaining: None,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::login;
+    use crate::store::test_store;
+
+    #[test]
+    fn test_generate_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes.iter().all(|c| c.len() == 23));
+        assert_ne!(codes[0], codes[1]);
+        assert_eq!(
+            hash_code(&codes[0].to_uppercase().replace('-', " ")),
+            hash_code(&codes[0])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redeem_recovery_code() {
+        let store = test_store(&["test_user"]).await;
+        let codes = issue_recovery_codes(&store, "test_user").await.unwrap();
+        assert_eq!(
+            redeem_recovery_code(&store, "test_user", "00000-00000-00000-00000", "new").await,
+            Some("Invalid or already used recovery code".to_string())
+        );
+        assert_eq!(
+            redeem_recovery_code(&store, "test_user", &codes[0], "new_password").await,
+            None
+        );
+        assert!(login(&store, "test_user", "new_password", None)
+            .await
+            .is_ok());
+        // codes only work once
+        assert!(
+            redeem_recovery_code(&store, "test_user", &codes[0], "other_password")
+                .await
+                .is_some()
+        );
+        let password = Credential::Password("new_password".to_string());
+        assert_eq!(
+            remaining_recovery_codes(&store, "test_user", Some(&password)).await,
+            Ok(RECOVERY_CODE_COUNT - 1)
+        );
+        // regenerating invalidates the previous set
+        let fresh = regenerate_recovery_codes(&store, "test_user", Some(&password))
+            .await
+            .unwrap();
+        assert!(
+            redeem_recovery_code(&store, "test_user", &codes[1], "other_password")
+                .await
+                .is_some()
+        );
+        assert_eq!(
+            redeem_recovery_code(&store, "test_user", &fresh[0], "other_password").await,
+            None
+        );
+    }
+}
@@ -0,0 +1,412 @@
+//! Rendering of Markdown to HTML, with the constructs each wiki enables on top
+//! of CommonMark (see [`RenderSettings`]). GitHub flavored Markdown is on by
+//! default. Wiki-links (see [`crate::wikilinks`]) and heading anchors (see
+//! [`crate::headings`]) always work.
+//!
+//! The HTML is cached in the store next to the Markdown, so changing the
+//! settings renders the home page and every page again.
+
+use crate::auth::{authenticate_owner, AuthToken, Credential};
+use crate::headings::{link_headings, mark_headings};
+use crate::sanitize::sanitize_html;
+use crate::store::{RenderSettings, WikiStore};
+use crate::wikilinks::link_wiki_pages;
+use crate::{AppState, DeleteWikiResponse};
+use axum::{extract::State, response::Json};
+use derivative::Derivative;
+use markdown::{to_html_with_options, CompileOptions, Constructs, Options, ParseOptions};
+use serde::Deserialize;
+use tracing::{error, info, instrument};
+
+fn options(settings: &RenderSettings) -> Options {
+    let mut constructs = if settings.mdx {
+        Constructs::mdx()
+    } else {
+        Constructs::default()
+    };
+    if settings.gfm {
+        constructs = Constructs {
+            gfm_autolink_literal: true,
+            gfm_footnote_definition: true,
+            gfm_label_start_footnote: true,
+            gfm_strikethrough: true,
+            gfm_table: true,
+            gfm_task_list_item: true,
+            ..constructs
+        };
+    }
+    constructs.math_flow = settings.math;
+    constructs.math_text = settings.math;
+    constructs.frontmatter = settings.frontmatter;
+    Options {
+        parse: ParseOptions {
+            constructs,
+            ..ParseOptions::default()
+        },
+        compile: CompileOptions {
+            // made safe by the sanitizer instead
+            allow_dangerous_html: settings.raw_html,
+            ..if settings.gfm {
+                CompileOptions::gfm()
+            } else {
+                CompileOptions::default()
+            }
+        },
+    }
+}
+
+/// Renders `markdown` of the wiki of `username` with `settings`. Only MDX has
+/// syntax errors.
+pub fn render_markdown(
+    markdown: &str,
+    username: &str,
+    settings: &RenderSettings,
+) -> Result<String, String> {
+    let options = options(settings);
+    let html = to_html_with_options(markdown, &options).map_err(|e| e.to_string())?;
+    let (html, headings) = mark_headings(&html, markdown, &options)?;
+    let html = if settings.raw_html {
+        sanitize_html(&html)
+    } else {
+        html
+    };
+    let html = link_wiki_pages(&html, username);
+    Ok(link_headings(&html, &headings, settings.toc))
+}
+
+/// Renders `markdown` for the wiki of `username`, with its settings. Wikis
+/// that do not exist (yet) get the default ones.
+pub async fn render_for(
+    store: &dyn WikiStore,
+    username: &str,
+    markdown: &str,
+) -> Result<String, String> {
+    let settings = match store.get_wiki(username).await {
+        Ok(Some(wiki)) => wiki.render_settings,
+        Ok(None) => RenderSettings::default(),
+        Err(e) => return Err(e.to_string()),
+    };
+    render_markdown(markdown, username, &settings)
+}
+
+/// Changes the render settings of the wiki of `username` and renders its home
+/// page and every page again. Nothing changes if any of them does not render.
+pub async fn set_wiki_render_settings(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    settings: RenderSettings,
+) -> Option<String> {
+    let wiki = match authenticate_owner(store, username, credential).await {
+        Ok(w) => w,
+        Err(e) => return Some(e),
+    };
+    // wikis from before the Markdown was kept only have their HTML
+    let content = match &wiki.markdown {
+        Some(markdown) => match render_markdown(markdown, username, &settings) {
+            Ok(html) => html,
+            Err(e) => return Some(format!("The home page cannot be rendered: {}", e)),
+        },
+        None => wiki.content,
+    };
+    let pages = match store.list_pages(username).await {
+        Ok(p) => p,
+        Err(e) => return Some(e.to_string()),
+    };
+    let mut rendered = Vec::with_capacity(pages.len());
+    for page in pages {
+        match render_markdown(&page.markdown, username, &settings) {
+            Ok(html) => rendered.push((page.path, html)),
+            Err(e) => return Some(format!("The page {} cannot be rendered: {}", page.path, e)),
+        }
+    }
+    store
+        .set_render_settings(username, settings, &content, &rendered)
+        .await
+        .err()
+        .map(|e| e.to_string())
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct SetRenderSettingsRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+    pub settings: RenderSettings,
+}
+
+#[instrument(skip(state))]
+pub async fn set_render_settings(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<SetRenderSettingsRequest>,
+) -> Json<DeleteWikiResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match set_wiki_render_settings(
+        state.store.as_ref(),
+        &payload.username,
+        credential.as_ref(),
+        payload.settings,
+    )
+    .await
+    {
+        Some(e) => {
+            error!(event = "SetRenderSettings", data_id = %payload.username, "{}", e);
+            Json(DeleteWikiResponse {
+                success: false,
+                error: Some(e),
+            })
+        }
+        None => {
+            info!(event = "SetRenderSettings", data_id = %payload.username, "Wiki is now rendered with {:?}", payload.settings.names());
+            Json(DeleteWikiResponse {
+                success: true,
+                error: None,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::test_store;
+
+    #[test]
+    fn test_gfm_by_default() {
+        let settings = RenderSettings::default();
+        let html = render_markdown(
+            "| a | b |\n| - | - |\n| 1 | 2 |\n\n- [x] done\n\n~~old~~ https://example.com and a note[^1]\n\n[^1]: note",
+            "test_user",
+            &settings,
+        )
+        .unwrap();
+        assert!(html.contains("<table>"));
+        assert!(html.contains("<input type=\"checkbox\" disabled=\"\" checked=\"\" />"));
+        assert!(html.contains("<del>old</del>"));
+        assert!(html.contains("<a href=\"https://example.com\">https://example.com</a>"));
+        assert!(html.contains("data-footnotes"));
+        // raw HTML stays escaped
+        assert_eq!(
+            render_markdown("<script>alert(1)</script>", "test_user", &settings).unwrap(),
+            "&lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+        let commonmark = RenderSettings {
+            gfm: false,
+            ..settings
+        };
+        assert_eq!(
+            render_markdown("~~old~~", "test_user", &commonmark).unwrap(),
+            "<p>~~old~~</p>"
+        );
+    }
+
+    #[test]
+    fn test_optional_constructs() {
+        let settings = RenderSettings {
+            math: true,
+            frontmatter: true,
+            ..RenderSettings::default()
+        };
+        let html =
+            render_markdown("---\ntitle: Notes\n---\n\n$x^2$", "test_user", &settings).unwrap();
+        assert!(!html.contains("title"));
+        assert!(html.contains("language-math"));
+        let html = render_markdown("$x^2$", "test_user", &RenderSettings::default()).unwrap();
+        assert_eq!(html, "<p>$x^2$</p>");
+        let mdx = RenderSettings {
+            mdx: true,
+            ..RenderSettings::default()
+        };
+        assert_eq!(
+            render_markdown("# Hi <Chart data={points} />", "test_user", &mdx).unwrap(),
+            "<h1 id=\"user-content-hi\">Hi <a class=\"anchor\" href=\"#user-content-hi\" aria-label=\"Permalink to this section\">#</a></h1>"
+        );
+        assert!(render_markdown("<Chart data={points", "test_user", &mdx).is_err());
+    }
+
+    #[test]
+    fn test_raw_html() {
+        let settings = RenderSettings {
+            raw_html: true,
+            ..RenderSettings::default()
+        };
+        let html = render_markdown(
+            "<details><summary>Keys</summary>\n\nPress <kbd>Ctrl</kbd><sup>1</sup>\n\n</details>\n\n- [ ] todo[^1]\n\n[^1]: note",
+            "test_user",
+            &settings,
+        )
+        .unwrap();
+        assert!(html.contains("<details><summary>Keys</summary>"));
+        assert!(html.contains("<kbd>Ctrl</kbd><sup>1</sup>"));
+        assert!(html.contains("<input disabled=\"\" type=\"checkbox\">"));
+        assert!(html.contains("id=\"user-content-fn-1\""));
+        let html = render_markdown(
+            "<img src=x onerror=alert(1)> [x](javascript:alert(1))\n\n<script>alert(1)</script>",
+            "test_user",
+            &settings,
+        )
+        .unwrap();
+        assert!(!html.contains("onerror"));
+        assert!(!html.contains("javascript:"));
+        assert!(!html.contains("<script"));
+    }
+
+    #[test]
+    fn test_table_of_contents() {
+        let settings = RenderSettings::default();
+        let html = render_markdown(
+            "# Notes\n\n[TOC]\n\n## Setup\n\n### Linux\n\n## Usage",
+            "test_user",
+            &settings,
+        )
+        .unwrap();
+        assert!(html.starts_with("<h1 id=\"user-content-notes\">"));
+        assert!(html.contains("<nav class=\"toc\">\n<p><strong>Contents</strong></p>\n<ul>\n<li><a href=\"#user-content-notes\">Notes</a><ul>\n<li><a href=\"#user-content-setup\">Setup</a><ul>\n<li><a href=\"#user-content-linux\">Linux</a></li>\n</ul>\n</li>\n<li><a href=\"#user-content-usage\">Usage</a></li>\n</ul>\n</li>\n</ul>\n</nav>"));
+        assert!(!html.contains("[TOC]"));
+        // without a marker, only wikis with the setting get one, at the top
+        let html = render_markdown("# Notes\n\n`{{toc}}`", "test_user", &settings).unwrap();
+        assert!(!html.contains("class=\"toc\""));
+        assert!(html.contains("<code>{{toc}}</code>"));
+        let toc = RenderSettings {
+            toc: true,
+            ..settings
+        };
+        let html = render_markdown("# Notes", "test_user", &toc).unwrap();
+        assert!(html.starts_with("<nav class=\"toc\">"));
+        assert_eq!(
+            render_markdown("no headings", "test_user", &toc).unwrap(),
+            "<p>no headings</p>"
+        );
+    }
+
+    #[test]
+    fn test_raw_headings_keep_no_ids() {
+        let settings = RenderSettings {
+            raw_html: true,
+            ..RenderSettings::default()
+        };
+        let html = render_markdown(
+            "<h2>Raw</h2>\n\n## Setup &amp; usage[^1]\n\n<h2>Also raw</h2>\n\n## See [[notes|the notes]]\n\n[^1]: note",
+            "test_user",
+            &settings,
+        )
+        .unwrap();
+        assert!(html.contains("<h2>Raw</h2>"));
+        assert!(html.contains("<h2>Also raw</h2>"));
+        assert!(html.contains("<h2 id=\"user-content-setup--usage\">Setup &amp; usage"));
+        assert!(html.contains("<h2 id=\"user-content-see-notesthe-notes\">See <a class=\"wikilink\" href=\"/wikis/test_user/notes\">the notes</a>"));
+
+        // raw headings and ids cannot take the ids of the Markdown headings
+        let html = render_markdown(
+            "<h2>Setup</h2>\n\n<p id=\"user-content-setup\">x</p>\n\n## Setup",
+            "test_user",
+            &settings,
+        )
+        .unwrap();
+        assert!(html.starts_with("<h2>Setup</h2>\n<p>x</p>\n<h2 id=\"user-content-setup\">Setup"));
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let settings = RenderSettings {
+            math: true,
+            ..RenderSettings::default()
+        };
+        assert_eq!(settings.names(), vec!["gfm", "math"]);
+        assert_eq!(
+            RenderSettings::parse("gfm,html").map(|s| s.raw_html),
+            Some(true)
+        );
+        assert_eq!(RenderSettings::parse("gfm,toc").map(|s| s.toc), Some(true));
+        assert_eq!(RenderSettings::parse("gfm,math"), Some(settings));
+        assert_eq!(
+            RenderSettings::parse(""),
+            Some(RenderSettings {
+                gfm: false,
+                ..RenderSettings::default()
+            })
+        );
+        assert_eq!(RenderSettings::parse("gfm,wat"), None);
+    }
+
+    #[tokio::test]
+    async fn test_changing_settings_renders_again() {
+        let store = test_store(&["test_user"]).await;
+        store
+            .update_wiki(
+                "test_user",
+                "~~home~~",
+                "<p><del>home</del></p>",
+                None,
+                "test_user",
+            )
+            .await
+            .unwrap();
+        store
+            .insert_page(
+                "test_user",
+                "notes",
+                "~~notes~~",
+                "<p><del>notes</del></p>",
+                None,
+                "test_user",
+            )
+            .await
+            .unwrap();
+        let password = Credential::Password("test_password".to_string());
+        let commonmark = RenderSettings {
+            gfm: false,
+            ..RenderSettings::default()
+        };
+        assert_eq!(
+            set_wiki_render_settings(&store, "test_user", None, commonmark).await,
+            Some("Missing password or session token".to_string())
+        );
+        assert_eq!(
+            set_wiki_render_settings(&store, "test_user", Some(&password), commonmark).await,
+            None
+        );
+        let wiki = store.get_wiki("test_user").await.unwrap().unwrap();
+        assert_eq!(wiki.render_settings, commonmark);
+        assert_eq!(wiki.content, "<p>~~home~~</p>");
+        let page = store.get_page("test_user", "notes").await.unwrap().unwrap();
+        assert_eq!(page.content, "<p>~~notes~~</p>");
+        assert_eq!(
+            render_for(&store, "test_user", "~~x~~").await,
+            Ok("<p>~~x~~</p>".to_string())
+        );
+        // nothing changes when a page is not valid MDX
+        store
+            .update_page(
+                "test_user",
+                "notes",
+                "{broken",
+                "<p>{broken</p>",
+                None,
+                "test_user",
+            )
+            .await
+            .unwrap();
+        let mdx = RenderSettings {
+            mdx: true,
+            ..RenderSettings::default()
+        };
+        assert!(
+            set_wiki_render_settings(&store, "test_user", Some(&password), mdx)
+                .await
+                .is_some()
+        );
+        assert_eq!(
+            store
+                .get_wiki("test_user")
+                .await
+                .unwrap()
+                .unwrap()
+                .render_settings,
+            commonmark
+        );
+    }
+}
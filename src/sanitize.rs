@@ -0,0 +1,252 @@
+//! Allowlist sanitizer for wikis that allow raw HTML in their Markdown (see
+//! [`crate::store::RenderSettings::raw_html`]).
+//!
+//! Only formatting tags and a few attributes go through: no scripts, styles,
+//! forms, frames or event handlers, and links and images only point to
+//! `http`, `https` and `mailto` URLs or relative ones. Classes are limited to
+//! the ones the Markdown renderer emits, so that the styles of the site cannot
+//! be used to draw outside of the wiki container.
+
+use ammonia::Builder;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+const ALLOWED_TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "b",
+    "blockquote",
+    "br",
+    "caption",
+    "code",
+    "col",
+    "colgroup",
+    "dd",
+    "del",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "input",
+    "ins",
+    "kbd",
+    "li",
+    "mark",
+    "ol",
+    "p",
+    "pre",
+    "q",
+    "rp",
+    "rt",
+    "ruby",
+    "s",
+    "samp",
+    "section",
+    "small",
+    "span",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "u",
+    "ul",
+    "var",
+];
+
+/// Attributes allowed on every allowed tag. `id` and `class` are further
+/// checked by [`filter_attribute`].
+const GENERIC_ATTRIBUTES: &[&str] = &["class", "id", "lang", "title"];
+
+const TAG_ATTRIBUTES: &[(&str, &[&str])] = &[
+    (
+        "a",
+        &[
+            "href",
+            "aria-describedby",
+            "aria-label",
+            "data-footnote-ref",
+            "data-footnote-backref",
+        ],
+    ),
+    ("img", &["src", "alt", "width", "height", "align"]),
+    ("details", &["open"]),
+    ("div", &["align"]),
+    ("p", &["align"]),
+    ("h1", &["align"]),
+    ("h2", &["align"]),
+    ("h3", &["align"]),
+    ("h4", &["align"]),
+    ("h5", &["align"]),
+    ("h6", &["align"]),
+    ("td", &["align", "colspan", "rowspan"]),
+    ("th", &["align", "colspan", "rowspan"]),
+    ("ol", &["start"]),
+    ("input", &["checked", "disabled"]),
+    ("section", &["data-footnotes"]),
+];
+
+const URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Classes the Markdown renderer emits for code blocks, math and footnotes.
+fn is_allowed_class(class: &str) -> bool {
+    matches!(
+        class,
+        "math-inline" | "math-display" | "footnotes" | "sr-only"
+    ) || class.strip_prefix("language-").is_some_and(|l| {
+        !l.is_empty()
+            && l.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+#-_".contains(c))
+    })
+}
+
+fn filter_attribute<'u>(_element: &str, attribute: &str, value: &'u str) -> Option<Cow<'u, str>> {
+    match attribute {
+        "class" => {
+            let kept: Vec<&str> = value
+                .split_whitespace()
+                .filter(|c| is_allowed_class(c))
+                .collect();
+            (!kept.is_empty()).then(|| kept.join(" ").into())
+        }
+        // only the ids of footnotes, which cannot clobber those of the site
+        "id" => (value.starts_with("user-content-") || value == "footnote-label")
+            .then_some(value.into()),
+        _ => Some(value.into()),
+    }
+}
+
+static SANITIZER: LazyLock<Builder<'static>> = LazyLock::new(|| {
+    let mut builder = Builder::empty();
+    builder
+        .tags(ALLOWED_TAGS.iter().copied().collect())
+        .generic_attributes(GENERIC_ATTRIBUTES.iter().copied().collect())
+        .tag_attributes(
+            TAG_ATTRIBUTES
+                .iter()
+                .map(|(tag, attributes)| (*tag, attributes.iter().copied().collect()))
+                .collect::<HashMap<_, HashSet<_>>>(),
+        )
+        .url_schemes(URL_SCHEMES.iter().copied().collect())
+        // task list items are the only inputs. A single forced attribute, as
+        // several are added in no particular order.
+        .set_tag_attribute_value("input", "type", "checkbox")
+        .attribute_filter(filter_attribute);
+    builder
+});
+
+/// Strips everything but the allowed tags and attributes from `html`.
+pub fn sanitize_html(html: &str) -> String {
+    SANITIZER.clean(html).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_formatting() {
+        for html in [
+            "<details><summary>More</summary><p>Hidden</p></details>",
+            "<p><kbd>Ctrl</kbd> + <kbd>C</kbd>, x<sup>2</sup> and H<sub>2</sub>O</p>",
+            "<p align=\"center\"><img src=\"/logo.png\" alt=\"Logo\" width=\"100\"></p>",
+            "<pre><code class=\"language-rust\">fn main() {}</code></pre>",
+            "<table><tbody><tr><td align=\"right\">1</td></tr></tbody></table>",
+        ] {
+            assert_eq!(sanitize_html(html), html);
+        }
+        assert_eq!(
+            sanitize_html("<a href=\"https://example.com\">site</a>"),
+            "<a href=\"https://example.com\" rel=\"noopener noreferrer\">site</a>"
+        );
+        assert_eq!(
+            sanitize_html("<input type=\"checkbox\" disabled=\"\" checked=\"\">"),
+            "<input disabled=\"\" checked=\"\" type=\"checkbox\">"
+        );
+    }
+
+    #[test]
+    fn test_strips_xss_payloads() {
+        for (payload, expected) in [
+            ("<script>alert(1)</script>", ""),
+            ("<SCRIPT SRC=//evil.example/x.js></SCRIPT>", ""),
+            ("<img src=x onerror=alert(1)>", "<img src=\"x\">"),
+            ("<svg onload=alert(1)><circle/></svg>", ""),
+            ("<body onload=alert(1)>", ""),
+            ("<iframe src=\"https://evil.example\"></iframe>", ""),
+            (
+                "<a href=\"javascript:alert(1)\">x</a>",
+                "<a rel=\"noopener noreferrer\">x</a>",
+            ),
+            (
+                "<a href=\"JaVaScRiPt:alert(1)\">x</a>",
+                "<a rel=\"noopener noreferrer\">x</a>",
+            ),
+            (
+                "<a href=\"&#106;avascript:alert(1)\">x</a>",
+                "<a rel=\"noopener noreferrer\">x</a>",
+            ),
+            (
+                "<a href=\"java\tscript:alert(1)\">x</a>",
+                "<a rel=\"noopener noreferrer\">x</a>",
+            ),
+            (
+                "<a href=\"data:text/html,<script>alert(1)</script>\">x</a>",
+                "<a rel=\"noopener noreferrer\">x</a>",
+            ),
+            ("<img src=\"data:image/svg+xml;base64,PHN2Zz4=\">", "<img>"),
+            ("<p style=\"position:fixed;inset:0\">x</p>", "<p>x</p>"),
+            ("<style>body { display: none }</style>", ""),
+            ("<div class=\"fixed inset-0 z-50\">x</div>", "<div>x</div>"),
+            (
+                "<form action=\"/login\"><input type=\"password\" name=\"p\"></form>",
+                "<input type=\"checkbox\">",
+            ),
+            ("<object data=\"x.swf\"></object><embed src=\"x.swf\">", ""),
+            (
+                "<meta http-equiv=\"refresh\" content=\"0;url=https://evil.example\">",
+                "",
+            ),
+            ("<base href=\"https://evil.example/\">", ""),
+            (
+                "<link rel=\"stylesheet\" href=\"https://evil.example/x.css\">",
+                "",
+            ),
+            (
+                "<math><mtext><table><mglyph><style><img src=x onerror=alert(1)>",
+                "",
+            ),
+            (
+                "<details open ontoggle=alert(1)>x</details>",
+                "<details open=\"\">x</details>",
+            ),
+            ("<h1 id=\"login\">x</h1>", "<h1>x</h1>"),
+            ("<!--><script>alert(1)</script>-->", "--&gt;"),
+            (
+                "<noscript><p title=\"</noscript><img src=x onerror=alert(1)>\"></noscript>",
+                "&lt;p title=\"<img src=\"x\">\"&gt;",
+            ),
+        ] {
+            assert_eq!(sanitize_html(payload), expected, "{}", payload);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Full-text search across wikis, as an HTML page (`/search`) and as JSON
+//! (`/api/search`).
+
+use crate::store::{SearchHit, MATCH_END, MATCH_START};
+use crate::{escape_html, page_url, style_page, AppState};
+use axum::{
+    extract::{Query, State},
+    response::{Html, Json},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, instrument};
+
+const MAX_RESULTS: usize = 20;
+const MAX_TERMS: usize = 16;
+
+#[derive(Deserialize, Debug)]
+pub struct SearchParams {
+    #[serde(default)]
+    q: String,
+    /// Restricts the search to the wiki of this user.
+    user: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchResult {
+    username: String,
+    page: String,
+    url: String,
+    /// HTML excerpt with the matched terms wrapped in `<mark>`.
+    snippet: String,
+    score: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchResponse {
+    success: bool,
+    error: Option<String>,
+    results: Vec<SearchResult>,
+}
+
+/// Splits a query into the plain words that have to match.
+fn search_terms(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric() && c != '_' && c != '-')
+        .filter(|t| !t.is_empty())
+        .take(MAX_TERMS)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Escapes a snippet and turns its match markers into `<mark>` tags.
+fn highlight_snippet(snippet: &str) -> String {
+    escape_html(snippet)
+        .replace(MATCH_START, "<mark>")
+        .replace(MATCH_END, "</mark>")
+}
+
+fn to_result(hit: SearchHit) -> SearchResult {
+    SearchResult {
+        url: page_url(&hit.username, &hit.page),
+        snippet: highlight_snippet(&hit.snippet),
+        username: hit.username,
+        page: hit.page,
+        score: hit.score,
+    }
+}
+
+async fn run_search(state: &AppState, params: &SearchParams) -> Result<Vec<SearchResult>, String> {
+    let terms = search_terms(&params.q);
+    let hits = state
+        .store
+        .search(&terms, params.user.as_deref(), MAX_RESULTS)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(hits.into_iter().map(to_result).collect())
+}
+
+fn render_search_page(
+    params: &SearchParams,
+    results: &Result<Vec<SearchResult>, String>,
+) -> String {
+    let mut html = format!(
+        "<h1>Search</h1>\n<form action=\"/search\" method=\"get\">\n<input type=\"search\" name=\"q\" value=\"{}\" placeholder=\"Search wikis...\" class=\"input input-bordered\" />\n",
+        escape_html(&params.q)
+    );
+    if let Some(user) = &params.user {
+        html.push_str(&format!(
+            "<input type=\"hidden\" name=\"user\" value=\"{}\" />\n",
+            escape_html(user)
+        ));
+    }
+    html.push_str("<button type=\"submit\" class=\"btn btn-primary\">Search</button>\n</form>\n");
+    match results {
+        Ok(results) if results.is_empty() => {
+            if !params.q.trim().is_empty() {
+                html.push_str("<p>No results found.</p>");
+            }
+        }
+        Ok(results) => {
+            html.push_str("<ul>\n");
+            for result in results {
+                let title = if result.page.is_empty() {
+                    result.username.clone()
+                } else {
+                    format!("{}/{}", result.username, result.page)
+                };
+                html.push_str(&format!(
+                    "<li><a href=\"{}\">{}</a>\n<p>{}</p></li>\n",
+                    escape_html(&result.url),
+                    escape_html(&title),
+                    result.snippet
+                ));
+            }
+            html.push_str("</ul>");
+        }
+        Err(e) => html.push_str(&format!("<p>Search failed: {}</p>", escape_html(e))),
+    }
+    html
+}
+
+#[instrument(skip(state))]
+pub async fn search_page(
+    State(state): State<AppState>,
+    Query(params): Query<SearchParams>,
+) -> Html<String> {
+    let results = run_search(&state, &params).await;
+    match &results {
+        Ok(r) => info!(
+            event = "Search",
+            "{} results for query {:?}",
+            r.len(),
+            params.q
+        ),
+        Err(e) => error!(event = "Search", "{}", e),
+    }
+    Html(style_page(&render_search_page(&params, &results), "Search"))
+}
+
+#[instrument(skip(state))]
+pub async fn search_json(
+    State(state): State<AppState>,
+    Query(params): Query<SearchParams>,
+) -> Json<SearchResponse> {
+    match run_search(&state, &params).await {
+        Ok(results) => {
+            info!(
+                event = "Search",
+                "{} results for query {:?}",
+                results.len(),
+                params.q
+            );
+            Json(SearchResponse {
+                success: true,
+                error: None,
+                results,
+            })
+        }
+        Err(e) => {
+            error!(event = "Search", "{}", e);
+            Json(SearchResponse {
+                success: false,
+                error: Some(e),
+                results: vec![],
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_search_terms() {
+        assert_eq!(
+            search_terms("  rust \"OR\" NEAR(borrow-checker)* "),
+            vec!["rust", "OR", "NEAR", "borrow-checker"]
+        );
+        assert!(search_terms("  ").is_empty());
+    }
+
+    #[test]
+    fn test_highlight_snippet_escapes_content() {
+        let snippet = format!("<script>{}rust{}</script>", MATCH_START, MATCH_END);
+        assert_eq!(
+            highlight_snippet(&snippet),
+            "&lt;script&gt;<mark>rust</mark>&lt;/script&gt;"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_handlers() {
+        let state = AppState::new(Arc::new(MemoryStore::new()));
+        state
+            .store
+            .insert_wiki("alice", "# Rust notes", "<h1>Rust notes</h1>", "hash")
+            .await
+            .unwrap();
+        state
+            .store
+            .insert_wiki("bob", "# Rust too", "<h1>Rust too</h1>", "hash")
+            .await
+            .unwrap();
+        let params = |user: Option<&str>| {
+            Query(SearchParams {
+                q: "rust".to_string(),
+                user: user.map(str::to_string),
+            })
+        };
+        let response = search_json(State(state.clone()), params(None)).await;
+        assert!(response.success);
+        assert_eq!(response.results.len(), 2);
+        let response = search_json(State(state.clone()), params(Some("alice"))).await;
+        assert_eq!(response.results.len(), 1);
+        assert_eq!(response.results[0].url, "/wikis/alice");
+        assert_eq!(response.results[0].snippet, "# <mark>Rust</mark> notes");
+        let page = search_page(State(state), params(Some("alice"))).await;
+        assert!(page.0.contains("<a href=\"/wikis/alice\">alice</a>"));
+    }
+}
@@ -0,0 +1,1987 @@
+use super::migrations::run_migrations;
+use super::{
+    unix_now, ApiKey, AuditEvent, Collaborator, LoginThrottle, Page, RenderSettings, Revision,
+    Role, SearchHit, Session, ShareLink, StoreError, Totp, Visibility, Wiki, WikiStore, MATCH_END,
+    MATCH_START,
+};
+use crate::usernames::username_key;
+use async_trait::async_trait;
+use libsql::{params, Builder, Connection, Database, Row};
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+/// Where the wikis are stored, selected through the `LIBSQL_MODE` environment variable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatabaseConfig {
+    /// An embedded libSQL/SQLite database file (or `:memory:`).
+    Local { path: String },
+    /// A remote libSQL server such as Turso.
+    Remote { url: String, token: String },
+    /// A local embedded replica of a remote libSQL server: reads are served
+    /// from the local file, writes go to the primary.
+    Replica {
+        path: String,
+        url: String,
+        token: String,
+        sync_interval: Duration,
+    },
+}
+
+impl DatabaseConfig {
+    /// Reads the configuration from the environment.
+    ///
+    /// `LIBSQL_MODE=local` uses `LIBSQL_DATABASE_PATH` (defaults to `personal-wiki.db`),
+    /// while `LIBSQL_MODE=remote` (the default) requires `LIBSQL_CONNECTION_STRING`
+    /// and `LIBSQL_AUTH_TOKEN`. `LIBSQL_MODE=replica` needs all three, and syncs
+    /// every `LIBSQL_SYNC_INTERVAL_SECS` seconds (defaults to 60).
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let required = |name: &str| var(name).ok_or_else(|| format!("{} should be set", name));
+        let mode = var("LIBSQL_MODE").unwrap_or_else(|| "remote".to_string());
+        match mode.as_str() {
+            "local" => Ok(Self::Local {
+                path: var("LIBSQL_DATABASE_PATH").unwrap_or_else(|| "personal-wiki.db".to_string()),
+            }),
+            "remote" => Ok(Self::Remote {
+                url: required("LIBSQL_CONNECTION_STRING")?,
+                token: required("LIBSQL_AUTH_TOKEN")?,
+            }),
+            "replica" => {
+                let sync_interval = match var("LIBSQL_SYNC_INTERVAL_SECS") {
+                    Some(secs) => secs.parse::<u64>().map_err(|_| {
+                        format!(
+                            "LIBSQL_SYNC_INTERVAL_SECS should be a number of seconds, got '{}'",
+                            secs
+                        )
+                    })?,
+                    None => 60,
+                };
+                Ok(Self::Replica {
+                    path: var("LIBSQL_DATABASE_PATH")
+                        .unwrap_or_else(|| "personal-wiki.db".to_string()),
+                    url: required("LIBSQL_CONNECTION_STRING")?,
+                    token: required("LIBSQL_AUTH_TOKEN")?,
+                    sync_interval: Duration::from_secs(sync_interval),
+                })
+            }
+            other => Err(format!(
+                "Unsupported LIBSQL_MODE '{}', expected 'local', 'remote' or 'replica'",
+                other
+            )),
+        }
+    }
+
+    /// Opens a connection without touching the schema, for maintenance tasks
+    /// that must run before pending migrations.
+    pub async fn connect(&self) -> Result<Connection, StoreError> {
+        Ok(self.open().await?.connect()?)
+    }
+
+    async fn open(&self) -> libsql::Result<Database> {
+        match self {
+            Self::Local { path } => Builder::new_local(path).build().await,
+            Self::Remote { url, token } => {
+                Builder::new_remote(url.clone(), token.clone())
+                    .build()
+                    .await
+            }
+            // read_your_writes makes the replica catch up right after each of
+            // its writes, on top of the periodic sync
+            Self::Replica {
+                path,
+                url,
+                token,
+                sync_interval,
+            } => {
+                let db = Builder::new_remote_replica(path, url.clone(), token.clone())
+                    .sync_interval(*sync_interval)
+                    .read_your_writes(true)
+                    .build()
+                    .await?;
+                // serve whatever the local file holds if the primary is unreachable
+                if let Err(e) = db.sync().await {
+                    warn!("initial sync of the embedded replica failed: {}", e);
+                }
+                Ok(db)
+            }
+        }
+    }
+}
+
+/// Maps unique constraint violations to [`StoreError::AlreadyExists`].
+fn map_conflict(e: libsql::Error) -> StoreError {
+    if e.to_string().contains("UNIQUE constraint failed") {
+        StoreError::AlreadyExists
+    } else {
+        e.into()
+    }
+}
+
+fn revision_from_row(row: &Row) -> Result<Revision, StoreError> {
+    Ok(Revision {
+        id: row.get(0)?,
+        page: row.get(1)?,
+        markdown: row.get(2)?,
+        summary: row.get(3)?,
+        created_at: row.get(4)?,
+        author: row.get(5)?,
+    })
+}
+
+fn collaborator_from_row(row: &Row) -> Result<Collaborator, StoreError> {
+    let role: String = row.get(1)?;
+    Ok(Collaborator {
+        username: row.get(0)?,
+        role: Role::parse(&role)
+            .ok_or_else(|| StoreError::Backend(format!("Unknown collaborator role '{}'", role)))?,
+        created_at: row.get(2)?,
+    })
+}
+
+fn api_key_from_row(row: &Row) -> Result<ApiKey, StoreError> {
+    let scopes: String = row.get(3)?;
+    Ok(ApiKey {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        name: row.get(2)?,
+        scopes: scopes.split_whitespace().map(str::to_string).collect(),
+        created_at: row.get(4)?,
+        last_used_at: row.get(5)?,
+    })
+}
+
+fn share_link_from_row(row: &Row) -> Result<ShareLink, StoreError> {
+    Ok(ShareLink {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        name: row.get(2)?,
+        created_at: row.get(3)?,
+    })
+}
+
+fn page_from_row(row: &Row) -> Result<Page, StoreError> {
+    Ok(Page {
+        path: row.get(0)?,
+        content: row.get(1)?,
+        markdown: row.get(2)?,
+        updated_at: row.get(3)?,
+    })
+}
+
+/// Builds an FTS5 query matching every term literally, so that user input
+/// cannot inject FTS5 syntax.
+fn fts_query(terms: &[String]) -> String {
+    terms
+        .iter()
+        .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Sets a new password hash inside the transaction `tx`, revoking every
+/// session and API key of the wiki and recording `event` in its audit log.
+async fn reset_password(
+    tx: &Connection,
+    username: &str,
+    password_hash: &str,
+    event: &str,
+) -> Result<(), StoreError> {
+    let updated = tx
+        .execute(
+            "UPDATE wikis SET password = ?1 WHERE user = ?2",
+            params![password_hash, username],
+        )
+        .await?;
+    if updated == 0 {
+        return Err(StoreError::NotFound);
+    }
+    tx.execute("DELETE FROM sessions WHERE user = ?", params![username])
+        .await?;
+    tx.execute("DELETE FROM api_keys WHERE user = ?", params![username])
+        .await?;
+    tx.execute(
+        "INSERT INTO audit_log (user, event, created_at) VALUES (?1, ?2, ?3)",
+        params![username, event, unix_now()],
+    )
+    .await?;
+    Ok(())
+}
+
+/// libSQL-backed store.
+///
+/// The database is opened once and a single connection is shared by every
+/// request, instead of reconnecting for every storage call. Since the
+/// connection is shared, a statement run while another request's transaction
+/// is open would become part of it. Every write therefore holds `lock` for
+/// writing, single statements included, and every read holds it for reading,
+/// so that reads never see rows of a transaction that is not committed yet.
+pub struct LibsqlStore {
+    /// Kept alive for the background sync task of embedded replicas.
+    _db: Database,
+    conn: Connection,
+    lock: RwLock<()>,
+}
+
+impl LibsqlStore {
+    /// Opens the database and brings its schema up to date.
+    pub async fn open(config: &DatabaseConfig) -> Result<Self, StoreError> {
+        let db = config.open().await?;
+        let conn = db.connect()?;
+        run_migrations(&conn).await?;
+        let store = Self {
+            _db: db,
+            conn,
+            lock: RwLock::new(()),
+        };
+        store.backfill_user_keys().await?;
+        Ok(store)
+    }
+
+    /// Sets the `user_key` of wikis created before it existed. When legacy
+    /// usernames collide, the oldest one gets the key and the others are left
+    /// without one, so that they keep working but block nobody. Those are
+    /// marked with `user_key_conflict` and skipped from then on.
+    async fn backfill_user_keys(&self) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT id, user FROM wikis WHERE user_key IS NULL AND user_key_conflict = 0 ORDER BY id",
+                (),
+            )
+            .await?;
+        let mut legacy = Vec::new();
+        while let Some(row) = rows.next().await? {
+            legacy.push((row.get::<i64>(0)?, row.get::<String>(1)?));
+        }
+        for (id, username) in &legacy {
+            let updated = self
+                .conn
+                .execute(
+                    "UPDATE wikis SET user_key = ?1 WHERE id = ?2",
+                    params![username_key(username), *id],
+                )
+                .await
+                .map_err(map_conflict);
+            match updated {
+                Ok(_) => {}
+                Err(StoreError::AlreadyExists) => {
+                    warn!("username {} is confusable with an older one", username);
+                    self.conn
+                        .execute(
+                            "UPDATE wikis SET user_key_conflict = 1 WHERE id = ?",
+                            params![*id],
+                        )
+                        .await?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// One-time backfill for wikis stored before the Markdown source was kept:
+    /// `convert` turns their cached HTML back into Markdown. Returns how many
+    /// wikis were updated.
+    pub async fn backfill_markdown(
+        &self,
+        convert: fn(&str) -> String,
+    ) -> Result<usize, StoreError> {
+        let legacy = {
+            let _guard = self.lock.read().await;
+            let mut rows = self
+                .conn
+                .query("SELECT id, content FROM wikis WHERE markdown IS NULL", ())
+                .await?;
+            let mut legacy = Vec::new();
+            while let Some(row) = rows.next().await? {
+                legacy.push((row.get::<i64>(0)?, row.get::<String>(1)?));
+            }
+            legacy
+        };
+        for (id, content) in &legacy {
+            let markdown = convert(content);
+            let _guard = self.lock.write().await;
+            let tx = self.conn.transaction().await?;
+            tx.execute(
+                "UPDATE wikis SET markdown = ?1 WHERE id = ?2",
+                params![markdown.as_str(), *id],
+            )
+            .await?;
+            tx.execute(
+                "INSERT INTO revisions (user, markdown, summary, created_at) SELECT user, ?1, 'Imported existing version', ?2 FROM wikis WHERE id = ?3",
+                params![markdown.as_str(), unix_now(), *id],
+            )
+            .await?;
+            tx.commit().await?;
+        }
+        Ok(legacy.len())
+    }
+}
+
+#[async_trait]
+impl WikiStore for LibsqlStore {
+    async fn get_wiki(&self, username: &str) -> Result<Option<Wiki>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT content, markdown, password, visibility, render_settings FROM wikis WHERE user = ?",
+                params![username],
+            )
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            let content: String = row.get(0)?;
+            let markdown: Option<String> = row.get(1)?;
+            let pwd: String = row.get(2)?;
+            let visibility: String = row.get(3)?;
+            let visibility = Visibility::parse(&visibility).ok_or_else(|| {
+                StoreError::Backend(format!("Unknown wiki visibility '{}'", visibility))
+            })?;
+            let render_settings: String = row.get(4)?;
+            let render_settings = RenderSettings::parse(&render_settings).ok_or_else(|| {
+                StoreError::Backend(format!("Unknown render settings '{}'", render_settings))
+            })?;
+            return Ok(Some(Wiki {
+                visibility,
+                render_settings,
+                ..Wiki::new(content, markdown, pwd)
+            }));
+        }
+
+        Ok(None)
+    }
+
+    async fn find_username_by_key(&self, key: &str) -> Result<Option<String>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query("SELECT user FROM wikis WHERE user_key = ?", params![key])
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn insert_wiki(
+        &self,
+        username: &str,
+        markdown: &str,
+        content: &str,
+        password: &str,
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let tx = self.conn.transaction().await?;
+        tx.execute(
+            "INSERT INTO wikis (user, content, markdown, password, user_key) VALUES (?1, ?2, ?3, ?4, ?5)",
+            [username, content, markdown, password, &username_key(username)],
+        )
+        .await
+        .map_err(map_conflict)?;
+        tx.execute(
+            "INSERT INTO revisions (user, page, markdown, summary, created_at, author) VALUES (?1, '', ?2, NULL, ?3, ?1)",
+            params![username, markdown, unix_now()],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update_wiki(
+        &self,
+        username: &str,
+        markdown: &str,
+        content: &str,
+        summary: Option<&str>,
+        author: &str,
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let tx = self.conn.transaction().await?;
+        let updated = tx
+            .execute(
+                "UPDATE wikis SET content = ?1, markdown = ?2 WHERE user = ?3",
+                [content, markdown, username],
+            )
+            .await?;
+        if updated == 0 {
+            return Err(StoreError::NotFound);
+        }
+        tx.execute(
+            "INSERT INTO revisions (user, page, markdown, summary, created_at, author) VALUES (?1, '', ?2, ?3, ?4, ?5)",
+            params![username, markdown, summary, unix_now(), author],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_wiki(&self, username: &str) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let tx = self.conn.transaction().await?;
+        let deleted = tx
+            .execute("DELETE FROM wikis WHERE user = ?", params![username])
+            .await?;
+        if deleted == 0 {
+            return Err(StoreError::NotFound);
+        }
+        tx.execute("DELETE FROM pages WHERE user = ?", params![username])
+            .await?;
+        tx.execute("DELETE FROM sessions WHERE user = ?", params![username])
+            .await?;
+        tx.execute("DELETE FROM api_keys WHERE user = ?", params![username])
+            .await?;
+        tx.execute("DELETE FROM audit_log WHERE user = ?", params![username])
+            .await?;
+        tx.execute(
+            "DELETE FROM recovery_codes WHERE user = ?",
+            params![username],
+        )
+        .await?;
+        tx.execute(
+            "DELETE FROM login_throttles WHERE user = ?",
+            params![username],
+        )
+        .await?;
+        tx.execute("DELETE FROM totp WHERE user = ?", params![username])
+            .await?;
+        tx.execute("DELETE FROM share_links WHERE user = ?", params![username])
+            .await?;
+        tx.execute(
+            "DELETE FROM collaborators WHERE wiki = ?1 OR user = ?1",
+            params![username],
+        )
+        .await?;
+        tx.execute("DELETE FROM revisions WHERE user = ?", params![username])
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_page(&self, username: &str, path: &str) -> Result<Option<Page>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT path, content, markdown, updated_at FROM pages WHERE user = ?1 AND path = ?2",
+                params![username, path],
+            )
+            .await?;
+        if let Some(row) = rows.next().await? {
+            return Ok(Some(page_from_row(&row)?));
+        }
+        Ok(None)
+    }
+
+    async fn list_pages(&self, username: &str) -> Result<Vec<Page>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT path, content, markdown, updated_at FROM pages WHERE user = ? ORDER BY path",
+                params![username],
+            )
+            .await?;
+        let mut pages = Vec::new();
+        while let Some(row) = rows.next().await? {
+            pages.push(page_from_row(&row)?);
+        }
+        Ok(pages)
+    }
+
+    async fn insert_page(
+        &self,
+        username: &str,
+        path: &str,
+        markdown: &str,
+        content: &str,
+        summary: Option<&str>,
+        author: &str,
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let now = unix_now();
+        let tx = self.conn.transaction().await?;
+        tx.execute(
+            "INSERT INTO pages (user, path, markdown, content, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![username, path, markdown, content, now],
+        )
+        .await
+        .map_err(map_conflict)?;
+        tx.execute(
+            "INSERT INTO revisions (user, page, markdown, summary, created_at, author) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![username, path, markdown, summary, now, author],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update_page(
+        &self,
+        username: &str,
+        path: &str,
+        markdown: &str,
+        content: &str,
+        summary: Option<&str>,
+        author: &str,
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let now = unix_now();
+        let tx = self.conn.transaction().await?;
+        let updated = tx
+            .execute(
+                "UPDATE pages SET markdown = ?1, content = ?2, updated_at = ?3 WHERE user = ?4 AND path = ?5",
+                params![markdown, content, now, username, path],
+            )
+            .await?;
+        if updated == 0 {
+            return Err(StoreError::NotFound);
+        }
+        tx.execute(
+            "INSERT INTO revisions (user, page, markdown, summary, created_at, author) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![username, path, markdown, summary, now, author],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_page(&self, username: &str, path: &str) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let deleted = self
+            .conn
+            .execute(
+                "DELETE FROM pages WHERE user = ?1 AND path = ?2",
+                params![username, path],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn list_revisions(&self, username: &str) -> Result<Vec<Revision>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT id, page, markdown, summary, created_at, author FROM revisions WHERE user = ? ORDER BY id DESC",
+                params![username],
+            )
+            .await?;
+        let mut revisions = Vec::new();
+        while let Some(row) = rows.next().await? {
+            revisions.push(revision_from_row(&row)?);
+        }
+        Ok(revisions)
+    }
+
+    async fn get_revision(&self, username: &str, id: i64) -> Result<Option<Revision>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT id, page, markdown, summary, created_at, author FROM revisions WHERE user = ?1 AND id = ?2",
+                params![username, id],
+            )
+            .await?;
+        if let Some(row) = rows.next().await? {
+            return Ok(Some(revision_from_row(&row)?));
+        }
+        Ok(None)
+    }
+
+    async fn create_session(
+        &self,
+        username: &str,
+        token_hash: &str,
+        expires_at: i64,
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        self.conn
+            .execute(
+                "INSERT INTO sessions (token_hash, user, created_at, expires_at) VALUES (?1, ?2, ?3, ?4)",
+                params![token_hash, username, unix_now(), expires_at],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_session(&self, token_hash: &str) -> Result<Option<Session>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT user, expires_at FROM sessions WHERE token_hash = ?",
+                params![token_hash],
+            )
+            .await?;
+        if let Some(row) = rows.next().await? {
+            return Ok(Some(Session {
+                username: row.get(0)?,
+                expires_at: row.get(1)?,
+            }));
+        }
+        Ok(None)
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let deleted = self
+            .conn
+            .execute(
+                "DELETE FROM sessions WHERE token_hash = ?",
+                params![token_hash],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn create_api_key(
+        &self,
+        username: &str,
+        name: &str,
+        key_hash: &str,
+        scopes: &[String],
+    ) -> Result<ApiKey, StoreError> {
+        let _guard = self.lock.write().await;
+        let created_at = unix_now();
+        let mut rows = self
+            .conn
+            .query(
+                "INSERT INTO api_keys (user, name, key_hash, scopes, created_at) VALUES (?1, ?2, ?3, ?4, ?5) RETURNING id",
+                params![username, name, key_hash, scopes.join(" "), created_at],
+            )
+            .await
+            .map_err(map_conflict)?;
+        let id = match rows.next().await.map_err(map_conflict)? {
+            Some(row) => row.get(0)?,
+            None => return Err(StoreError::Backend("API key was not stored".to_string())),
+        };
+        Ok(ApiKey {
+            id,
+            username: username.to_string(),
+            name: name.to_string(),
+            scopes: scopes.to_vec(),
+            created_at,
+            last_used_at: None,
+        })
+    }
+
+    async fn list_api_keys(&self, username: &str) -> Result<Vec<ApiKey>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT id, user, name, scopes, created_at, last_used_at FROM api_keys WHERE user = ? ORDER BY id",
+                params![username],
+            )
+            .await?;
+        let mut keys = Vec::new();
+        while let Some(row) = rows.next().await? {
+            keys.push(api_key_from_row(&row)?);
+        }
+        Ok(keys)
+    }
+
+    async fn get_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT id, user, name, scopes, created_at, last_used_at FROM api_keys WHERE key_hash = ?",
+                params![key_hash],
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(api_key_from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn touch_api_key(&self, id: i64, at: i64) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let updated = self
+            .conn
+            .execute(
+                "UPDATE api_keys SET last_used_at = ?1 WHERE id = ?2",
+                params![at, id],
+            )
+            .await?;
+        if updated == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn delete_api_key(&self, username: &str, id: i64) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let deleted = self
+            .conn
+            .execute(
+                "DELETE FROM api_keys WHERE user = ?1 AND id = ?2",
+                params![username, id],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn change_password(
+        &self,
+        username: &str,
+        password_hash: &str,
+        event: &str,
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let tx = self.conn.transaction().await?;
+        reset_password(&tx, username, password_hash, event).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn rehash_password(
+        &self,
+        username: &str,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        self.conn
+            .execute(
+                "UPDATE wikis SET password = ?1 WHERE user = ?2 AND password = ?3",
+                params![new_hash, username, old_hash],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn replace_recovery_codes(
+        &self,
+        username: &str,
+        code_hashes: &[String],
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let tx = self.conn.transaction().await?;
+        tx.execute(
+            "DELETE FROM recovery_codes WHERE user = ?",
+            params![username],
+        )
+        .await?;
+        for code_hash in code_hashes {
+            tx.execute(
+                "INSERT INTO recovery_codes (user, code_hash) VALUES (?1, ?2)",
+                params![username, code_hash.as_str()],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn count_recovery_codes(&self, username: &str) -> Result<usize, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT COUNT(*) FROM recovery_codes WHERE user = ?",
+                params![username],
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(row.get::<u64>(0)? as usize),
+            None => Ok(0),
+        }
+    }
+
+    async fn has_recovery_code(&self, username: &str, code_hash: &str) -> Result<bool, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT 1 FROM recovery_codes WHERE user = ?1 AND code_hash = ?2",
+                params![username, code_hash],
+            )
+            .await?;
+        Ok(rows.next().await?.is_some())
+    }
+
+    async fn redeem_recovery_code(
+        &self,
+        username: &str,
+        code_hash: &str,
+        password_hash: &str,
+        event: &str,
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let tx = self.conn.transaction().await?;
+        let deleted = tx
+            .execute(
+                "DELETE FROM recovery_codes WHERE user = ?1 AND code_hash = ?2",
+                params![username, code_hash],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(StoreError::NotFound);
+        }
+        reset_password(&tx, username, password_hash, event).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_login_throttle(
+        &self,
+        username: &str,
+    ) -> Result<Option<LoginThrottle>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT failures, locked_until FROM login_throttles WHERE user = ?",
+                params![username],
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(LoginThrottle {
+                failures: row.get(0)?,
+                locked_until: row.get(1)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn record_login_failure(&self, username: &str) -> Result<u32, StoreError> {
+        let _guard = self.lock.write().await;
+        // a single statement, so that concurrent failures are all counted
+        let mut rows = self
+            .conn
+            .query(
+                "INSERT INTO login_throttles (user, failures) VALUES (?1, 1) ON CONFLICT (user) DO UPDATE SET failures = failures + 1 RETURNING failures",
+                params![username],
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(row.get(0)?),
+            None => Err(StoreError::Backend(
+                "failed login was not recorded".to_string(),
+            )),
+        }
+    }
+
+    async fn lock_login(&self, username: &str, until: i64) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let updated = self
+            .conn
+            .execute(
+                "UPDATE login_throttles SET locked_until = ?1 WHERE user = ?2",
+                params![until, username],
+            )
+            .await?;
+        if updated == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn reset_login_failures(&self, username: &str) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        self.conn
+            .execute(
+                "DELETE FROM login_throttles WHERE user = ?",
+                params![username],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn consume_recovery_code(
+        &self,
+        username: &str,
+        code_hash: &str,
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let deleted = self
+            .conn
+            .execute(
+                "DELETE FROM recovery_codes WHERE user = ?1 AND code_hash = ?2",
+                params![username, code_hash],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn get_totp(&self, username: &str) -> Result<Option<Totp>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT secret, enabled, last_step FROM totp WHERE user = ?",
+                params![username],
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(Totp {
+                secret: row.get(0)?,
+                enabled: row.get::<i64>(1)? != 0,
+                last_step: row.get(2)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn set_totp_secret(&self, username: &str, secret: &str) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        self.conn
+            .execute(
+                "INSERT INTO totp (user, secret) VALUES (?1, ?2) ON CONFLICT (user) DO UPDATE SET secret = excluded.secret, enabled = 0, last_step = NULL",
+                params![username, secret],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn enable_totp(&self, username: &str) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let updated = self
+            .conn
+            .execute(
+                "UPDATE totp SET enabled = 1 WHERE user = ?",
+                params![username],
+            )
+            .await?;
+        if updated == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn use_totp_step(&self, username: &str, step: i64) -> Result<bool, StoreError> {
+        let _guard = self.lock.write().await;
+        // a single conditional update, so that two requests racing with the
+        // same code cannot both succeed
+        let updated = self
+            .conn
+            .execute(
+                "UPDATE totp SET last_step = ?1 WHERE user = ?2 AND (last_step IS NULL OR last_step < ?1)",
+                params![step, username],
+            )
+            .await?;
+        Ok(updated > 0)
+    }
+
+    async fn delete_totp(&self, username: &str) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let deleted = self
+            .conn
+            .execute("DELETE FROM totp WHERE user = ?", params![username])
+            .await?;
+        if deleted == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn record_audit_event(&self, username: &str, event: &str) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        self.conn
+            .execute(
+                "INSERT INTO audit_log (user, event, created_at) VALUES (?1, ?2, ?3)",
+                params![username, event, unix_now()],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_visibility(
+        &self,
+        username: &str,
+        visibility: Visibility,
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let updated = self
+            .conn
+            .execute(
+                "UPDATE wikis SET visibility = ?1 WHERE user = ?2",
+                params![visibility.as_str(), username],
+            )
+            .await?;
+        if updated == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn set_render_settings(
+        &self,
+        username: &str,
+        settings: RenderSettings,
+        content: &str,
+        pages: &[(String, String)],
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let tx = self.conn.transaction().await?;
+        let updated = tx
+            .execute(
+                "UPDATE wikis SET render_settings = ?1, content = ?2 WHERE user = ?3",
+                params![settings.names().join(","), content, username],
+            )
+            .await?;
+        if updated == 0 {
+            return Err(StoreError::NotFound);
+        }
+        for (path, html) in pages {
+            tx.execute(
+                "UPDATE pages SET content = ?1 WHERE user = ?2 AND path = ?3",
+                params![html.as_str(), username, path.as_str()],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn create_share_link(
+        &self,
+        username: &str,
+        name: &str,
+        token_hash: &str,
+    ) -> Result<ShareLink, StoreError> {
+        let _guard = self.lock.write().await;
+        let created_at = unix_now();
+        let mut rows = self
+            .conn
+            .query(
+                "INSERT INTO share_links (user, name, token_hash, created_at) VALUES (?1, ?2, ?3, ?4) RETURNING id",
+                params![username, name, token_hash, created_at],
+            )
+            .await
+            .map_err(map_conflict)?;
+        let id = match rows.next().await.map_err(map_conflict)? {
+            Some(row) => row.get(0)?,
+            None => return Err(StoreError::Backend("Share link was not stored".to_string())),
+        };
+        Ok(ShareLink {
+            id,
+            username: username.to_string(),
+            name: name.to_string(),
+            created_at,
+        })
+    }
+
+    async fn list_share_links(&self, username: &str) -> Result<Vec<ShareLink>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT id, user, name, created_at FROM share_links WHERE user = ? ORDER BY id",
+                params![username],
+            )
+            .await?;
+        let mut links = Vec::new();
+        while let Some(row) = rows.next().await? {
+            links.push(share_link_from_row(&row)?);
+        }
+        Ok(links)
+    }
+
+    async fn get_share_link(&self, token_hash: &str) -> Result<Option<ShareLink>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT id, user, name, created_at FROM share_links WHERE token_hash = ?",
+                params![token_hash],
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(share_link_from_row(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn delete_share_link(&self, username: &str, id: i64) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let deleted = self
+            .conn
+            .execute(
+                "DELETE FROM share_links WHERE user = ?1 AND id = ?2",
+                params![username, id],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn set_collaborator(
+        &self,
+        wiki: &str,
+        username: &str,
+        role: Role,
+    ) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        self.conn
+            .execute(
+                "INSERT INTO collaborators (wiki, user, role, created_at) VALUES (?1, ?2, ?3, ?4) ON CONFLICT (wiki, user) DO UPDATE SET role = excluded.role",
+                params![wiki, username, role.as_str(), unix_now()],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn get_collaborator_role(
+        &self,
+        wiki: &str,
+        username: &str,
+    ) -> Result<Option<Role>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT user, role, created_at FROM collaborators WHERE wiki = ?1 AND user = ?2",
+                params![wiki, username],
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(collaborator_from_row(&row)?.role)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_collaborators(&self, wiki: &str) -> Result<Vec<Collaborator>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT user, role, created_at FROM collaborators WHERE wiki = ? ORDER BY user",
+                params![wiki],
+            )
+            .await?;
+        let mut collaborators = Vec::new();
+        while let Some(row) = rows.next().await? {
+            collaborators.push(collaborator_from_row(&row)?);
+        }
+        Ok(collaborators)
+    }
+
+    async fn delete_collaborator(&self, wiki: &str, username: &str) -> Result<(), StoreError> {
+        let _guard = self.lock.write().await;
+        let deleted = self
+            .conn
+            .execute(
+                "DELETE FROM collaborators WHERE wiki = ?1 AND user = ?2",
+                params![wiki, username],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn list_audit_events(&self, username: &str) -> Result<Vec<AuditEvent>, StoreError> {
+        let _guard = self.lock.read().await;
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT id, event, created_at FROM audit_log WHERE user = ? ORDER BY id DESC",
+                params![username],
+            )
+            .await?;
+        let mut events = Vec::new();
+        while let Some(row) = rows.next().await? {
+            events.push(AuditEvent {
+                id: row.get(0)?,
+                event: row.get(1)?,
+                created_at: row.get(2)?,
+            });
+        }
+        Ok(events)
+    }
+
+    async fn search(
+        &self,
+        terms: &[String],
+        username: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, StoreError> {
+        let _guard = self.lock.read().await;
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut rows = self
+            .conn
+            .query(
+                "SELECT user, page, snippet(search_index, 2, ?1, ?2, '…', 16), bm25(search_index) FROM search_index WHERE search_index MATCH ?3 AND (?4 IS NULL OR user = ?4) AND user IN (SELECT user FROM wikis WHERE visibility = 'public') ORDER BY bm25(search_index) LIMIT ?5",
+                params![
+                    MATCH_START.to_string(),
+                    MATCH_END.to_string(),
+                    fts_query(terms),
+                    username,
+                    limit as i64
+                ],
+            )
+            .await?;
+        let mut hits = Vec::new();
+        while let Some(row) = rows.next().await? {
+            hits.push(SearchHit {
+                username: row.get(0)?,
+                page: row.get(1)?,
+                snippet: row.get(2)?,
+                // bm25 is lower for better matches
+                score: -row.get::<f64>(3)?,
+            });
+        }
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_database_config_from_vars() {
+        let config = |vars: &[(&str, &str)]| {
+            let vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            DatabaseConfig::from_vars(|name| vars.get(name).cloned())
+        };
+        assert_eq!(
+            config(&[("LIBSQL_MODE", "local")]),
+            Ok(DatabaseConfig::Local {
+                path: "personal-wiki.db".to_string()
+            })
+        );
+        assert!(config(&[("LIBSQL_CONNECTION_STRING", "libsql://db")]).is_err());
+        assert_eq!(
+            config(&[
+                ("LIBSQL_MODE", "replica"),
+                ("LIBSQL_DATABASE_PATH", "/data/replica.db"),
+                ("LIBSQL_CONNECTION_STRING", "libsql://db"),
+                ("LIBSQL_AUTH_TOKEN", "token"),
+                ("LIBSQL_SYNC_INTERVAL_SECS", "5"),
+            ]),
+            Ok(DatabaseConfig::Replica {
+                path: "/data/replica.db".to_string(),
+                url: "libsql://db".to_string(),
+                token: "token".to_string(),
+                sync_interval: Duration::from_secs(5),
+            })
+        );
+        assert!(config(&[
+            ("LIBSQL_MODE", "replica"),
+            ("LIBSQL_CONNECTION_STRING", "libsql://db"),
+            ("LIBSQL_AUTH_TOKEN", "token"),
+            ("LIBSQL_SYNC_INTERVAL_SECS", "soon"),
+        ])
+        .is_err());
+        assert!(config(&[("LIBSQL_MODE", "cloud")]).is_err());
+    }
+
+    async fn open_store() -> LibsqlStore {
+        let config = DatabaseConfig::Local {
+            path: ":memory:".to_string(),
+        };
+        LibsqlStore::open(&config)
+            .await
+            .expect("Should be able to open an in-memory database")
+    }
+
+    /// A store with the wiki of `test_user`.
+    async fn store_with_wiki() -> LibsqlStore {
+        let store = open_store().await;
+        store
+            .insert_wiki("test_user", "# hello", "<h1>hello</h1>", "hash")
+            .await
+            .unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn test_wiki_crud() {
+        let store = open_store().await;
+        assert_eq!(store.get_wiki("test_user").await.unwrap(), None);
+        store
+            .insert_wiki("test_user", "# hello", "<h1>hello</h1>", "hash")
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_wiki("test_user").await.unwrap(),
+            Some(Wiki::new(
+                "<h1>hello</h1>".to_string(),
+                Some("# hello".to_string()),
+                "hash".to_string()
+            ))
+        );
+        assert_eq!(
+            store
+                .insert_wiki("test_user", "# other", "<h1>other</h1>", "hash2")
+                .await,
+            Err(StoreError::AlreadyExists)
+        );
+        // the rejected insert must not leave a revision behind
+        assert_eq!(store.list_revisions("test_user").await.unwrap().len(), 1);
+        store
+            .update_wiki(
+                "test_user",
+                "# hi!",
+                "<h1>hi!</h1>",
+                Some("greet"),
+                "editor_user",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_wiki("test_user").await.unwrap().unwrap().content,
+            "<h1>hi!</h1>"
+        );
+        let revisions = store.list_revisions("test_user").await.unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].markdown, "# hi!");
+        assert_eq!(revisions[0].summary, Some("greet".to_string()));
+        assert_eq!(revisions[0].author, Some("editor_user".to_string()));
+        assert_eq!(revisions[1].markdown, "# hello");
+        assert_eq!(
+            store
+                .get_revision("test_user", revisions[1].id)
+                .await
+                .unwrap(),
+            Some(revisions[1].clone())
+        );
+        assert_eq!(
+            store
+                .get_revision("other_user", revisions[1].id)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            store
+                .update_wiki("missing_user", "x", "<p>x</p>", None, "missing_user")
+                .await,
+            Err(StoreError::NotFound)
+        );
+        // a failed update must not leave a dangling revision behind
+        assert!(store
+            .list_revisions("missing_user")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unique_usernames() {
+        let store = store_with_wiki().await;
+        // the index holds on its own, without the checks of `insert_wiki`
+        let duplicate = store
+            .conn
+            .execute(
+                "INSERT INTO wikis (user, content, markdown, password, user_key) VALUES ('test_user', '', '', 'hash', 'other_key')",
+                (),
+            )
+            .await
+            .map_err(map_conflict);
+        assert_eq!(duplicate, Err(StoreError::AlreadyExists));
+        assert_eq!(store.find_username_by_key("other_key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_user_keys() {
+        let store = open_store().await;
+        // rows written before the user_key column existed
+        for username in ["alice", "Alice"] {
+            store
+                .conn
+                .execute(
+                    "INSERT INTO wikis (user, content, password) VALUES (?1, '<h1>old</h1>', 'hash')",
+                    params![username],
+                )
+                .await
+                .unwrap();
+        }
+        store.backfill_user_keys().await.unwrap();
+        assert_eq!(
+            store
+                .find_username_by_key(&username_key("ALICE"))
+                .await
+                .unwrap(),
+            Some("alice".to_string())
+        );
+        // the confusable one is not backfilled again on the next start
+        let mut rows = store
+            .conn
+            .query(
+                "SELECT user, user_key_conflict FROM wikis WHERE user_key IS NULL",
+                (),
+            )
+            .await
+            .unwrap();
+        let row = rows.next().await.unwrap().unwrap();
+        assert_eq!(row.get::<String>(0).unwrap(), "Alice");
+        assert_eq!(row.get::<i64>(1).unwrap(), 1);
+        assert!(rows.next().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_username_keys() {
+        let store = store_with_wiki().await;
+        // no wiki under a confusable name
+        assert_eq!(
+            store
+                .insert_wiki("TEST_USER", "# other", "<h1>other</h1>", "hash2")
+                .await,
+            Err(StoreError::AlreadyExists)
+        );
+        assert_eq!(
+            store
+                .find_username_by_key(&username_key("test_usEr"))
+                .await
+                .unwrap(),
+            Some("test_user".to_string())
+        );
+        assert_eq!(
+            store
+                .find_username_by_key(&username_key("other_user"))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pages() {
+        let store = store_with_wiki().await;
+        store
+            .insert_page(
+                "test_user",
+                "guides/setup",
+                "# setup",
+                "<h1>setup</h1>",
+                None,
+                "test_user",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .insert_page(
+                    "test_user",
+                    "guides/setup",
+                    "# again",
+                    "<h1>again</h1>",
+                    None,
+                    "test_user",
+                )
+                .await,
+            Err(StoreError::AlreadyExists)
+        );
+        store
+            .update_page(
+                "test_user",
+                "guides/setup",
+                "# set up",
+                "<h1>set up</h1>",
+                Some("typo"),
+                "test_user",
+            )
+            .await
+            .unwrap();
+        let page = store
+            .get_page("test_user", "guides/setup")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(page.content, "<h1>set up</h1>");
+        assert_eq!(store.list_pages("test_user").await.unwrap(), vec![page]);
+        let revisions = store.list_revisions("test_user").await.unwrap();
+        assert_eq!(revisions[0].page, "guides/setup");
+        assert_eq!(revisions[0].summary, Some("typo".to_string()));
+        store
+            .delete_page("test_user", "guides/setup")
+            .await
+            .unwrap();
+        assert_eq!(
+            store.delete_page("test_user", "guides/setup").await,
+            Err(StoreError::NotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        let store = store_with_wiki().await;
+        store
+            .create_session("test_user", "digest", 42)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_session("digest").await.unwrap(),
+            Some(Session {
+                username: "test_user".to_string(),
+                expires_at: 42
+            })
+        );
+        store.delete_session("digest").await.unwrap();
+        assert_eq!(store.get_session("digest").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_api_keys() {
+        let store = store_with_wiki().await;
+        let scopes = vec!["read-source".to_string(), "write".to_string()];
+        let key = store
+            .create_api_key("test_user", "ci", "key_digest", &scopes)
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .create_api_key("test_user", "ci", "other_digest", &scopes)
+                .await,
+            Err(StoreError::AlreadyExists)
+        );
+        store.touch_api_key(key.id, 42).await.unwrap();
+        let fetched = store.get_api_key("key_digest").await.unwrap().unwrap();
+        assert_eq!(fetched.scopes, scopes);
+        assert_eq!(fetched.last_used_at, Some(42));
+        assert_eq!(
+            store.list_api_keys("test_user").await.unwrap(),
+            vec![fetched]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_change_password() {
+        let store = store_with_wiki().await;
+        store
+            .create_session("test_user", "digest", 42)
+            .await
+            .unwrap();
+        store
+            .create_api_key("test_user", "ci", "key_digest", &[])
+            .await
+            .unwrap();
+        store
+            .change_password("test_user", "new_hash", "password_changed")
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_wiki("test_user").await.unwrap().unwrap().password,
+            "new_hash"
+        );
+        // signs out everywhere
+        assert_eq!(store.get_session("digest").await.unwrap(), None);
+        assert_eq!(store.get_api_key("key_digest").await.unwrap(), None);
+        let events = store.list_audit_events("test_user").await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "password_changed");
+        store
+            .rehash_password("test_user", "stale_hash", "ignored_hash")
+            .await
+            .unwrap();
+        store
+            .rehash_password("test_user", "new_hash", "rehashed")
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_wiki("test_user").await.unwrap().unwrap().password,
+            "rehashed"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recovery_codes() {
+        let store = store_with_wiki().await;
+        store
+            .replace_recovery_codes("test_user", &["a".to_string(), "b".to_string()])
+            .await
+            .unwrap();
+        store
+            .redeem_recovery_code("test_user", "a", "newer_hash", "password_recovered")
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .redeem_recovery_code("test_user", "a", "newer_hash", "password_recovered")
+                .await,
+            Err(StoreError::NotFound)
+        );
+        assert_eq!(
+            store.get_wiki("test_user").await.unwrap().unwrap().password,
+            "newer_hash"
+        );
+        assert_eq!(store.count_recovery_codes("test_user").await.unwrap(), 1);
+        assert!(!store.has_recovery_code("test_user", "a").await.unwrap());
+        assert!(store.has_recovery_code("test_user", "b").await.unwrap());
+        assert!(!store.has_recovery_code("other_user", "b").await.unwrap());
+        let events = store.list_audit_events("test_user").await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "password_recovered");
+    }
+
+    #[tokio::test]
+    async fn test_totp() {
+        let store = store_with_wiki().await;
+        store.set_totp_secret("test_user", "SECRET").await.unwrap();
+        store.enable_totp("test_user").await.unwrap();
+        assert!(store.use_totp_step("test_user", 10).await.unwrap());
+        assert!(!store.use_totp_step("test_user", 10).await.unwrap());
+        // regenerating recovery codes leaves two-factor authentication alone
+        store
+            .replace_recovery_codes("test_user", &["c".to_string()])
+            .await
+            .unwrap();
+        store.consume_recovery_code("test_user", "c").await.unwrap();
+        assert_eq!(
+            store.consume_recovery_code("test_user", "c").await,
+            Err(StoreError::NotFound)
+        );
+        assert_eq!(
+            store.get_totp("test_user").await.unwrap(),
+            Some(Totp {
+                secret: "SECRET".to_string(),
+                enabled: true,
+                last_step: Some(10)
+            })
+        );
+        store.delete_totp("test_user").await.unwrap();
+        assert_eq!(store.get_totp("test_user").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_visibility_and_share_links() {
+        let store = store_with_wiki().await;
+        store
+            .set_visibility("test_user", Visibility::Private)
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .get_wiki("test_user")
+                .await
+                .unwrap()
+                .unwrap()
+                .visibility,
+            Visibility::Private
+        );
+        let link = store
+            .create_share_link("test_user", "team", "share_digest")
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .create_share_link("test_user", "team", "other_digest")
+                .await,
+            Err(StoreError::AlreadyExists)
+        );
+        assert_eq!(
+            store.get_share_link("share_digest").await.unwrap(),
+            Some(link.clone())
+        );
+        assert_eq!(
+            store.list_share_links("test_user").await.unwrap(),
+            vec![link.clone()]
+        );
+        store.delete_share_link("test_user", link.id).await.unwrap();
+        assert_eq!(store.get_share_link("share_digest").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_render_settings() {
+        let store = store_with_wiki().await;
+        let settings = RenderSettings {
+            math: true,
+            ..RenderSettings::default()
+        };
+        store
+            .set_render_settings("test_user", settings, "<p>re-rendered</p>", &[])
+            .await
+            .unwrap();
+        let wiki = store.get_wiki("test_user").await.unwrap().unwrap();
+        assert_eq!(wiki.render_settings, settings);
+        assert_eq!(wiki.content, "<p>re-rendered</p>");
+        assert_eq!(
+            store
+                .set_render_settings("missing_user", settings, "", &[])
+                .await,
+            Err(StoreError::NotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collaborators() {
+        let store = store_with_wiki().await;
+        store
+            .set_collaborator("test_user", "editor_user", Role::Editor)
+            .await
+            .unwrap();
+        store
+            .set_collaborator("test_user", "editor_user", Role::Admin)
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .get_collaborator_role("test_user", "editor_user")
+                .await
+                .unwrap(),
+            Some(Role::Admin)
+        );
+        let collaborators = store.list_collaborators("test_user").await.unwrap();
+        assert_eq!(collaborators.len(), 1);
+        assert_eq!(collaborators[0].username, "editor_user");
+        store
+            .delete_collaborator("test_user", "editor_user")
+            .await
+            .unwrap();
+        assert_eq!(
+            store.delete_collaborator("test_user", "editor_user").await,
+            Err(StoreError::NotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_throttles() {
+        let store = store_with_wiki().await;
+        assert_eq!(store.get_login_throttle("test_user").await.unwrap(), None);
+        assert_eq!(store.record_login_failure("test_user").await.unwrap(), 1);
+        assert_eq!(store.record_login_failure("test_user").await.unwrap(), 2);
+        store.lock_login("test_user", 42).await.unwrap();
+        assert_eq!(
+            store.get_login_throttle("test_user").await.unwrap(),
+            Some(LoginThrottle {
+                failures: 2,
+                locked_until: Some(42)
+            })
+        );
+        // regenerating recovery codes does not lift the lockout
+        store
+            .replace_recovery_codes("test_user", &["a".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_login_throttle("test_user").await.unwrap(),
+            Some(LoginThrottle {
+                failures: 2,
+                locked_until: Some(42)
+            })
+        );
+        store.reset_login_failures("test_user").await.unwrap();
+        assert_eq!(store.get_login_throttle("test_user").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_delete_wiki() {
+        let store = store_with_wiki().await;
+        store
+            .insert_page(
+                "test_user",
+                "notes",
+                "# notes",
+                "<h1>notes</h1>",
+                None,
+                "test_user",
+            )
+            .await
+            .unwrap();
+        store
+            .create_session("test_user", "digest", 42)
+            .await
+            .unwrap();
+        store
+            .create_api_key("test_user", "ci", "key_digest", &[])
+            .await
+            .unwrap();
+        store
+            .change_password("test_user", "new_hash", "password_changed")
+            .await
+            .unwrap();
+        store
+            .create_session("test_user", "digest", 42)
+            .await
+            .unwrap();
+        store
+            .replace_recovery_codes("test_user", &["a".to_string()])
+            .await
+            .unwrap();
+        store
+            .set_collaborator("test_user", "editor_user", Role::Editor)
+            .await
+            .unwrap();
+        store
+            .set_collaborator("other_user", "test_user", Role::Editor)
+            .await
+            .unwrap();
+        store.delete_wiki("test_user").await.unwrap();
+        assert_eq!(store.get_wiki("test_user").await.unwrap(), None);
+        assert!(store.list_revisions("test_user").await.unwrap().is_empty());
+        assert!(store.list_pages("test_user").await.unwrap().is_empty());
+        assert_eq!(store.get_session("digest").await.unwrap(), None);
+        assert_eq!(store.list_api_keys("test_user").await.unwrap(), vec![]);
+        assert_eq!(store.list_audit_events("test_user").await.unwrap(), vec![]);
+        assert_eq!(store.count_recovery_codes("test_user").await.unwrap(), 0);
+        // both the wiki's collaborators and its owner's roles elsewhere go
+        assert!(store
+            .list_collaborators("test_user")
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            store
+                .get_collaborator_role("other_user", "test_user")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_failed_transaction_keeps_concurrent_writes() {
+        let store = Arc::new(store_with_wiki().await);
+        let mut tasks = Vec::new();
+        for i in 0..100 {
+            // rolled back, as the wiki does not exist
+            let failing = store.clone();
+            tasks.push(tokio::spawn(async move {
+                assert_eq!(
+                    failing
+                        .update_wiki("missing_user", "x", "<p>x</p>", None, "missing_user")
+                        .await,
+                    Err(StoreError::NotFound)
+                );
+            }));
+            let unrelated = store.clone();
+            tasks.push(tokio::spawn(async move {
+                unrelated
+                    .create_session("test_user", &format!("digest_{}", i), 42)
+                    .await
+                    .unwrap();
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        for i in 0..100 {
+            assert!(store
+                .get_session(&format!("digest_{}", i))
+                .await
+                .unwrap()
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn test_fts_query() {
+        assert_eq!(
+            fts_query(&["rust".to_string(), "a\"b".to_string()]),
+            "\"rust\" \"a\"\"b\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let store = open_store().await;
+        store
+            .insert_wiki("alice", "# Rust notes\nOwnership and borrowing", "", "hash")
+            .await
+            .unwrap();
+        store
+            .insert_wiki("bob", "# Cooking\nRisotto takes time", "", "hash")
+            .await
+            .unwrap();
+        store
+            .insert_page(
+                "bob",
+                "rust",
+                "Bob also writes Rust sometimes",
+                "",
+                None,
+                "bob",
+            )
+            .await
+            .unwrap();
+        let terms = vec!["rust".to_string()];
+        let hits = store.search(&terms, None, 10).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert!(hits
+            .iter()
+            .any(|h| h.username == "alice" && h.page.is_empty()));
+        assert!(hits.iter().any(|h| h
+            .snippet
+            .contains(&format!("{}Rust{}", MATCH_START, MATCH_END))));
+        let hits = store.search(&terms, Some("bob"), 10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].page, "rust");
+        // unlisted and private wikis are left out
+        store
+            .set_visibility("bob", Visibility::Unlisted)
+            .await
+            .unwrap();
+        assert_eq!(store.search(&terms, None, 10).await.unwrap().len(), 1);
+        store
+            .set_visibility("bob", Visibility::Public)
+            .await
+            .unwrap();
+        // the index follows updates and deletions
+        store
+            .update_wiki("alice", "# Go notes", "", None, "alice")
+            .await
+            .unwrap();
+        store.delete_page("bob", "rust").await.unwrap();
+        assert!(store.search(&terms, None, 10).await.unwrap().is_empty());
+        // FTS5 syntax in the query is taken literally
+        let hits = store
+            .search(&["risotto\" OR \"x".to_string()], None, 10)
+            .await
+            .unwrap();
+        assert!(hits.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_backfill_markdown() {
+        let store = open_store().await;
+        // a row written before the markdown column existed
+        store
+            .conn
+            .execute(
+                "INSERT INTO wikis (user, content, password) VALUES ('legacy', '<h1>old</h1>', 'hash')",
+                (),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_wiki("legacy").await.unwrap().unwrap().markdown,
+            None
+        );
+        assert_eq!(
+            store.backfill_markdown(|_| "# old".to_string()).await,
+            Ok(1)
+        );
+        assert_eq!(
+            store.get_wiki("legacy").await.unwrap().unwrap().markdown,
+            Some("# old".to_string())
+        );
+        assert_eq!(store.list_revisions("legacy").await.unwrap().len(), 1);
+        assert_eq!(
+            store.backfill_markdown(|_| "# old".to_string()).await,
+            Ok(0)
+        );
+    }
+}
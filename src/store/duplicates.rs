@@ -0,0 +1,84 @@
+//! Detection and resolution of duplicate usernames, which could be created
+//! before usernames were unique. They must be resolved before the
+//! `unique_wiki_users` migration can be applied.
+
+use super::StoreError;
+use libsql::{params, Connection};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateUser {
+    pub user: String,
+    /// Row ids sharing the username, oldest first. The first one is the row
+    /// that has always been served and is the one kept on resolution.
+    pub ids: Vec<i64>,
+}
+
+pub async fn find_duplicate_users(conn: &Connection) -> Result<Vec<DuplicateUser>, StoreError> {
+    let mut rows = conn
+        .query(
+            "SELECT user, id FROM wikis WHERE user IN (SELECT user FROM wikis GROUP BY user HAVING COUNT(*) > 1) ORDER BY user, id",
+            (),
+        )
+        .await?;
+    let mut duplicates: Vec<DuplicateUser> = Vec::new();
+    while let Some(row) = rows.next().await? {
+        let user: String = row.get(0)?;
+        let id: i64 = row.get(1)?;
+        match duplicates.last_mut() {
+            Some(last) if last.user == user => last.ids.push(id),
+            _ => duplicates.push(DuplicateUser {
+                user,
+                ids: vec![id],
+            }),
+        }
+    }
+    Ok(duplicates)
+}
+
+/// Deletes every duplicate row but the oldest one of each username and returns
+/// how many rows were deleted.
+pub async fn resolve_duplicate_users(conn: &Connection) -> Result<u64, StoreError> {
+    let deleted = conn
+        .execute(
+            "DELETE FROM wikis WHERE id NOT IN (SELECT MIN(id) FROM wikis GROUP BY user)",
+            params![],
+        )
+        .await?;
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libsql::Builder;
+
+    #[tokio::test]
+    async fn test_find_and_resolve_duplicate_users() {
+        let db = Builder::new_local(":memory:").build().await.unwrap();
+        let conn = db.connect().unwrap();
+        // the schema as it was before usernames were unique
+        conn.execute_batch(include_str!("../../migrations/0001_create_wikis.sql"))
+            .await
+            .unwrap();
+        conn.execute_batch(
+            "INSERT INTO wikis (user, content, password) VALUES ('a', '1', 'x'), ('b', '2', 'x'), ('a', '3', 'x'), ('a', '4', 'x');",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            find_duplicate_users(&conn).await.unwrap(),
+            vec![DuplicateUser {
+                user: "a".to_string(),
+                ids: vec![1, 3, 4]
+            }]
+        );
+        assert_eq!(resolve_duplicate_users(&conn).await, Ok(2));
+        assert!(find_duplicate_users(&conn).await.unwrap().is_empty());
+        let mut rows = conn
+            .query("SELECT content FROM wikis WHERE user = 'a'", ())
+            .await
+            .unwrap();
+        let row = rows.next().await.unwrap().unwrap();
+        assert_eq!(row.get::<String>(0).unwrap(), "1");
+    }
+}
@@ -0,0 +1,994 @@
+use super::{
+    unix_now, ApiKey, AuditEvent, Collaborator, LoginThrottle, Page, RenderSettings, Revision,
+    Role, SearchHit, Session, ShareLink, StoreError, Totp, Visibility, Wiki, WikiStore, MATCH_END,
+    MATCH_START,
+};
+use crate::usernames::username_key;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+
+#[derive(Default)]
+struct Inner {
+    wikis: HashMap<String, Wiki>,
+    /// Pages of every wiki, keyed by path so that they are listed in order.
+    pages: HashMap<String, BTreeMap<String, Page>>,
+    revisions: HashMap<String, Vec<Revision>>,
+    last_revision_id: i64,
+    /// Sessions keyed by token digest.
+    sessions: HashMap<String, Session>,
+    /// API keys keyed by digest.
+    api_keys: HashMap<String, ApiKey>,
+    last_api_key_id: i64,
+    audit_log: HashMap<String, Vec<AuditEvent>>,
+    last_audit_event_id: i64,
+    /// Digests of the unused recovery codes of every wiki.
+    recovery_codes: HashMap<String, HashSet<String>>,
+    login_throttles: HashMap<String, LoginThrottle>,
+    totp: HashMap<String, Totp>,
+    /// Share links keyed by token digest.
+    share_links: HashMap<String, ShareLink>,
+    last_share_link_id: i64,
+    /// Collaborators of every wiki, keyed by username.
+    collaborators: HashMap<String, BTreeMap<String, Collaborator>>,
+}
+
+impl Inner {
+    fn push_revision(
+        &mut self,
+        username: &str,
+        page: &str,
+        markdown: &str,
+        summary: Option<&str>,
+        author: &str,
+    ) {
+        self.last_revision_id += 1;
+        let revision = Revision {
+            id: self.last_revision_id,
+            page: page.to_string(),
+            markdown: markdown.to_string(),
+            summary: summary.map(str::to_string),
+            author: Some(author.to_string()),
+            created_at: unix_now(),
+        };
+        self.revisions
+            .entry(username.to_string())
+            .or_default()
+            .push(revision);
+    }
+
+    fn reset_password(
+        &mut self,
+        username: &str,
+        password_hash: &str,
+        event: &str,
+    ) -> Result<(), StoreError> {
+        match self.wikis.get_mut(username) {
+            Some(wiki) => wiki.password = password_hash.to_string(),
+            None => return Err(StoreError::NotFound),
+        }
+        self.sessions.retain(|_, s| s.username != username);
+        self.api_keys.retain(|_, k| k.username != username);
+        self.push_audit_event(username, event);
+        Ok(())
+    }
+
+    fn push_audit_event(&mut self, username: &str, event: &str) {
+        self.last_audit_event_id += 1;
+        let entry = AuditEvent {
+            id: self.last_audit_event_id,
+            event: event.to_string(),
+            created_at: unix_now(),
+        };
+        self.audit_log
+            .entry(username.to_string())
+            .or_default()
+            .push(entry);
+    }
+}
+
+const SNIPPET_CONTEXT: usize = 60;
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Scores `text` against `terms` (ASCII case-insensitive) and builds a
+/// snippet around the first match, or returns `None` if a term is missing.
+fn match_text(text: &str, terms: &[String]) -> Option<(String, f64)> {
+    let lowered = text.to_ascii_lowercase();
+    let terms: Vec<String> = terms.iter().map(|t| t.to_ascii_lowercase()).collect();
+    let mut score = 0;
+    let mut first = text.len();
+    for term in &terms {
+        let occurrences: Vec<usize> = lowered
+            .match_indices(term.as_str())
+            .map(|(i, _)| i)
+            .collect();
+        first = first.min(*occurrences.first()?);
+        score += occurrences.len();
+    }
+    let start = floor_char_boundary(text, first.saturating_sub(SNIPPET_CONTEXT));
+    let end = floor_char_boundary(text, (first + 2 * SNIPPET_CONTEXT).min(text.len()));
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut i = start;
+    while i < end {
+        let matched = terms
+            .iter()
+            .find(|t| lowered[i..].starts_with(t.as_str()) && i + t.len() <= end);
+        match matched {
+            Some(term) => {
+                snippet.push(MATCH_START);
+                snippet.push_str(&text[i..i + term.len()]);
+                snippet.push(MATCH_END);
+                i += term.len();
+            }
+            None => {
+                let c = text[i..].chars().next().unwrap_or_default();
+                snippet.push(c);
+                i += c.len_utf8();
+            }
+        }
+    }
+    if end < text.len() {
+        snippet.push('…');
+    }
+    Some((snippet, score as f64))
+}
+
+/// Keeps every wiki in a `HashMap`; nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    inner: Mutex<Inner>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn inner(&self) -> Result<MutexGuard<'_, Inner>, StoreError> {
+        self.inner
+            .lock()
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl WikiStore for MemoryStore {
+    async fn get_wiki(&self, username: &str) -> Result<Option<Wiki>, StoreError> {
+        Ok(self.inner()?.wikis.get(username).cloned())
+    }
+
+    async fn find_username_by_key(&self, key: &str) -> Result<Option<String>, StoreError> {
+        Ok(self
+            .inner()?
+            .wikis
+            .keys()
+            .find(|u| username_key(u) == key)
+            .cloned())
+    }
+
+    async fn insert_wiki(
+        &self,
+        username: &str,
+        markdown: &str,
+        content: &str,
+        password: &str,
+    ) -> Result<(), StoreError> {
+        let mut inner = self.inner()?;
+        let key = username_key(username);
+        if inner.wikis.keys().any(|u| username_key(u) == key) {
+            return Err(StoreError::AlreadyExists);
+        }
+        inner.wikis.insert(
+            username.to_string(),
+            Wiki::new(
+                content.to_string(),
+                Some(markdown.to_string()),
+                password.to_string(),
+            ),
+        );
+        inner.push_revision(username, "", markdown, None, username);
+        Ok(())
+    }
+
+    async fn update_wiki(
+        &self,
+        username: &str,
+        markdown: &str,
+        content: &str,
+        summary: Option<&str>,
+        author: &str,
+    ) -> Result<(), StoreError> {
+        let mut inner = self.inner()?;
+        match inner.wikis.get_mut(username) {
+            Some(wiki) => {
+                wiki.content = content.to_string();
+                wiki.markdown = Some(markdown.to_string());
+            }
+            None => return Err(StoreError::NotFound),
+        }
+        inner.push_revision(username, "", markdown, summary, author);
+        Ok(())
+    }
+
+    async fn delete_wiki(&self, username: &str) -> Result<(), StoreError> {
+        let mut inner = self.inner()?;
+        inner.revisions.remove(username);
+        inner.pages.remove(username);
+        inner.sessions.retain(|_, s| s.username != username);
+        inner.api_keys.retain(|_, k| k.username != username);
+        inner.audit_log.remove(username);
+        inner.recovery_codes.remove(username);
+        inner.login_throttles.remove(username);
+        inner.totp.remove(username);
+        inner.share_links.retain(|_, l| l.username != username);
+        inner.collaborators.remove(username);
+        for collaborators in inner.collaborators.values_mut() {
+            collaborators.remove(username);
+        }
+        match inner.wikis.remove(username) {
+            Some(_) => Ok(()),
+            None => Err(StoreError::NotFound),
+        }
+    }
+
+    async fn get_page(&self, username: &str, path: &str) -> Result<Option<Page>, StoreError> {
+        let inner = self.inner()?;
+        Ok(inner
+            .pages
+            .get(username)
+            .and_then(|pages| pages.get(path))
+            .cloned())
+    }
+
+    async fn list_pages(&self, username: &str) -> Result<Vec<Page>, StoreError> {
+        let inner = self.inner()?;
+        Ok(inner
+            .pages
+            .get(username)
+            .map(|pages| pages.values().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn insert_page(
+        &self,
+        username: &str,
+        path: &str,
+        markdown: &str,
+        content: &str,
+        summary: Option<&str>,
+        author: &str,
+    ) -> Result<(), StoreError> {
+        let mut inner = self.inner()?;
+        let pages = inner.pages.entry(username.to_string()).or_default();
+        if pages.contains_key(path) {
+            return Err(StoreError::AlreadyExists);
+        }
+        pages.insert(
+            path.to_string(),
+            Page {
+                path: path.to_string(),
+                content: content.to_string(),
+                markdown: markdown.to_string(),
+                updated_at: unix_now(),
+            },
+        );
+        inner.push_revision(username, path, markdown, summary, author);
+        Ok(())
+    }
+
+    async fn update_page(
+        &self,
+        username: &str,
+        path: &str,
+        markdown: &str,
+        content: &str,
+        summary: Option<&str>,
+        author: &str,
+    ) -> Result<(), StoreError> {
+        let mut inner = self.inner()?;
+        match inner
+            .pages
+            .get_mut(username)
+            .and_then(|pages| pages.get_mut(path))
+        {
+            Some(page) => {
+                page.content = content.to_string();
+                page.markdown = markdown.to_string();
+                page.updated_at = unix_now();
+            }
+            None => return Err(StoreError::NotFound),
+        }
+        inner.push_revision(username, path, markdown, summary, author);
+        Ok(())
+    }
+
+    async fn delete_page(&self, username: &str, path: &str) -> Result<(), StoreError> {
+        let mut inner = self.inner()?;
+        match inner
+            .pages
+            .get_mut(username)
+            .and_then(|pages| pages.remove(path))
+        {
+            Some(_) => Ok(()),
+            None => Err(StoreError::NotFound),
+        }
+    }
+
+    async fn list_revisions(&self, username: &str) -> Result<Vec<Revision>, StoreError> {
+        let inner = self.inner()?;
+        let mut revisions = inner.revisions.get(username).cloned().unwrap_or_default();
+        revisions.reverse();
+        Ok(revisions)
+    }
+
+    async fn get_revision(&self, username: &str, id: i64) -> Result<Option<Revision>, StoreError> {
+        let inner = self.inner()?;
+        Ok(inner
+            .revisions
+            .get(username)
+            .and_then(|revisions| revisions.iter().find(|r| r.id == id))
+            .cloned())
+    }
+
+    async fn create_session(
+        &self,
+        username: &str,
+        token_hash: &str,
+        expires_at: i64,
+    ) -> Result<(), StoreError> {
+        self.inner()?.sessions.insert(
+            token_hash.to_string(),
+            Session {
+                username: username.to_string(),
+                expires_at,
+            },
+        );
+        Ok(())
+    }
+
+    async fn get_session(&self, token_hash: &str) -> Result<Option<Session>, StoreError> {
+        Ok(self.inner()?.sessions.get(token_hash).cloned())
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), StoreError> {
+        match self.inner()?.sessions.remove(token_hash) {
+            Some(_) => Ok(()),
+            None => Err(StoreError::NotFound),
+        }
+    }
+
+    async fn create_api_key(
+        &self,
+        username: &str,
+        name: &str,
+        key_hash: &str,
+        scopes: &[String],
+    ) -> Result<ApiKey, StoreError> {
+        let mut inner = self.inner()?;
+        if inner.api_keys.contains_key(key_hash)
+            || inner
+                .api_keys
+                .values()
+                .any(|k| k.username == username && k.name == name)
+        {
+            return Err(StoreError::AlreadyExists);
+        }
+        inner.last_api_key_id += 1;
+        let key = ApiKey {
+            id: inner.last_api_key_id,
+            username: username.to_string(),
+            name: name.to_string(),
+            scopes: scopes.to_vec(),
+            created_at: unix_now(),
+            last_used_at: None,
+        };
+        inner.api_keys.insert(key_hash.to_string(), key.clone());
+        Ok(key)
+    }
+
+    async fn list_api_keys(&self, username: &str) -> Result<Vec<ApiKey>, StoreError> {
+        let mut keys: Vec<ApiKey> = self
+            .inner()?
+            .api_keys
+            .values()
+            .filter(|k| k.username == username)
+            .cloned()
+            .collect();
+        keys.sort_by_key(|k| k.id);
+        Ok(keys)
+    }
+
+    async fn get_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, StoreError> {
+        Ok(self.inner()?.api_keys.get(key_hash).cloned())
+    }
+
+    async fn touch_api_key(&self, id: i64, at: i64) -> Result<(), StoreError> {
+        match self.inner()?.api_keys.values_mut().find(|k| k.id == id) {
+            Some(key) => {
+                key.last_used_at = Some(at);
+                Ok(())
+            }
+            None => Err(StoreError::NotFound),
+        }
+    }
+
+    async fn delete_api_key(&self, username: &str, id: i64) -> Result<(), StoreError> {
+        let mut inner = self.inner()?;
+        let before = inner.api_keys.len();
+        inner
+            .api_keys
+            .retain(|_, k| !(k.username == username && k.id == id));
+        if inner.api_keys.len() == before {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn change_password(
+        &self,
+        username: &str,
+        password_hash: &str,
+        event: &str,
+    ) -> Result<(), StoreError> {
+        self.inner()?.reset_password(username, password_hash, event)
+    }
+
+    async fn rehash_password(
+        &self,
+        username: &str,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> Result<(), StoreError> {
+        if let Some(wiki) = self.inner()?.wikis.get_mut(username) {
+            if wiki.password == old_hash {
+                wiki.password = new_hash.to_string();
+            }
+        }
+        Ok(())
+    }
+
+    async fn replace_recovery_codes(
+        &self,
+        username: &str,
+        code_hashes: &[String],
+    ) -> Result<(), StoreError> {
+        self.inner()?
+            .recovery_codes
+            .insert(username.to_string(), code_hashes.iter().cloned().collect());
+        Ok(())
+    }
+
+    async fn count_recovery_codes(&self, username: &str) -> Result<usize, StoreError> {
+        Ok(self
+            .inner()?
+            .recovery_codes
+            .get(username)
+            .map_or(0, HashSet::len))
+    }
+
+    async fn has_recovery_code(&self, username: &str, code_hash: &str) -> Result<bool, StoreError> {
+        Ok(self
+            .inner()?
+            .recovery_codes
+            .get(username)
+            .is_some_and(|codes| codes.contains(code_hash)))
+    }
+
+    async fn redeem_recovery_code(
+        &self,
+        username: &str,
+        code_hash: &str,
+        password_hash: &str,
+        event: &str,
+    ) -> Result<(), StoreError> {
+        let mut inner = self.inner()?;
+        let redeemed = inner
+            .recovery_codes
+            .get_mut(username)
+            .is_some_and(|codes| codes.remove(code_hash));
+        if !redeemed {
+            return Err(StoreError::NotFound);
+        }
+        inner.reset_password(username, password_hash, event)
+    }
+
+    async fn get_login_throttle(
+        &self,
+        username: &str,
+    ) -> Result<Option<LoginThrottle>, StoreError> {
+        Ok(self.inner()?.login_throttles.get(username).cloned())
+    }
+
+    async fn record_login_failure(&self, username: &str) -> Result<u32, StoreError> {
+        let mut inner = self.inner()?;
+        let throttle = inner
+            .login_throttles
+            .entry(username.to_string())
+            .or_insert(LoginThrottle {
+                failures: 0,
+                locked_until: None,
+            });
+        throttle.failures += 1;
+        Ok(throttle.failures)
+    }
+
+    async fn lock_login(&self, username: &str, until: i64) -> Result<(), StoreError> {
+        match self.inner()?.login_throttles.get_mut(username) {
+            Some(throttle) => {
+                throttle.locked_until = Some(until);
+                Ok(())
+            }
+            None => Err(StoreError::NotFound),
+        }
+    }
+
+    async fn reset_login_failures(&self, username: &str) -> Result<(), StoreError> {
+        self.inner()?.login_throttles.remove(username);
+        Ok(())
+    }
+
+    async fn consume_recovery_code(
+        &self,
+        username: &str,
+        code_hash: &str,
+    ) -> Result<(), StoreError> {
+        let consumed = self
+            .inner()?
+            .recovery_codes
+            .get_mut(username)
+            .is_some_and(|codes| codes.remove(code_hash));
+        if !consumed {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn get_totp(&self, username: &str) -> Result<Option<Totp>, StoreError> {
+        Ok(self.inner()?.totp.get(username).cloned())
+    }
+
+    async fn set_totp_secret(&self, username: &str, secret: &str) -> Result<(), StoreError> {
+        self.inner()?.totp.insert(
+            username.to_string(),
+            Totp {
+                secret: secret.to_string(),
+                enabled: false,
+                last_step: None,
+            },
+        );
+        Ok(())
+    }
+
+    async fn enable_totp(&self, username: &str) -> Result<(), StoreError> {
+        match self.inner()?.totp.get_mut(username) {
+            Some(totp) => {
+                totp.enabled = true;
+                Ok(())
+            }
+            None => Err(StoreError::NotFound),
+        }
+    }
+
+    async fn use_totp_step(&self, username: &str, step: i64) -> Result<bool, StoreError> {
+        match self.inner()?.totp.get_mut(username) {
+            Some(totp) if totp.last_step.is_some_and(|last| last >= step) => Ok(false),
+            Some(totp) => {
+                totp.last_step = Some(step);
+                Ok(true)
+            }
+            None => Err(StoreError::NotFound),
+        }
+    }
+
+    async fn delete_totp(&self, username: &str) -> Result<(), StoreError> {
+        match self.inner()?.totp.remove(username) {
+            Some(_) => Ok(()),
+            None => Err(StoreError::NotFound),
+        }
+    }
+
+    async fn record_audit_event(&self, username: &str, event: &str) -> Result<(), StoreError> {
+        self.inner()?.push_audit_event(username, event);
+        Ok(())
+    }
+
+    async fn set_visibility(
+        &self,
+        username: &str,
+        visibility: Visibility,
+    ) -> Result<(), StoreError> {
+        match self.inner()?.wikis.get_mut(username) {
+            Some(wiki) => {
+                wiki.visibility = visibility;
+                Ok(())
+            }
+            None => Err(StoreError::NotFound),
+        }
+    }
+
+    async fn set_render_settings(
+        &self,
+        username: &str,
+        settings: RenderSettings,
+        content: &str,
+        pages: &[(String, String)],
+    ) -> Result<(), StoreError> {
+        let mut inner = self.inner()?;
+        match inner.wikis.get_mut(username) {
+            Some(wiki) => {
+                wiki.render_settings = settings;
+                wiki.content = content.to_string();
+            }
+            None => return Err(StoreError::NotFound),
+        }
+        if let Some(stored) = inner.pages.get_mut(username) {
+            for (path, html) in pages {
+                if let Some(page) = stored.get_mut(path) {
+                    page.content = html.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn create_share_link(
+        &self,
+        username: &str,
+        name: &str,
+        token_hash: &str,
+    ) -> Result<ShareLink, StoreError> {
+        let mut inner = self.inner()?;
+        if inner.share_links.contains_key(token_hash)
+            || inner
+                .share_links
+                .values()
+                .any(|l| l.username == username && l.name == name)
+        {
+            return Err(StoreError::AlreadyExists);
+        }
+        inner.last_share_link_id += 1;
+        let link = ShareLink {
+            id: inner.last_share_link_id,
+            username: username.to_string(),
+            name: name.to_string(),
+            created_at: unix_now(),
+        };
+        inner
+            .share_links
+            .insert(token_hash.to_string(), link.clone());
+        Ok(link)
+    }
+
+    async fn list_share_links(&self, username: &str) -> Result<Vec<ShareLink>, StoreError> {
+        let mut links: Vec<ShareLink> = self
+            .inner()?
+            .share_links
+            .values()
+            .filter(|l| l.username == username)
+            .cloned()
+            .collect();
+        links.sort_by_key(|l| l.id);
+        Ok(links)
+    }
+
+    async fn get_share_link(&self, token_hash: &str) -> Result<Option<ShareLink>, StoreError> {
+        Ok(self.inner()?.share_links.get(token_hash).cloned())
+    }
+
+    async fn delete_share_link(&self, username: &str, id: i64) -> Result<(), StoreError> {
+        let mut inner = self.inner()?;
+        let before = inner.share_links.len();
+        inner
+            .share_links
+            .retain(|_, l| !(l.username == username && l.id == id));
+        if inner.share_links.len() == before {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn set_collaborator(
+        &self,
+        wiki: &str,
+        username: &str,
+        role: Role,
+    ) -> Result<(), StoreError> {
+        let mut inner = self.inner()?;
+        let collaborators = inner.collaborators.entry(wiki.to_string()).or_default();
+        match collaborators.get_mut(username) {
+            Some(collaborator) => collaborator.role = role,
+            None => {
+                collaborators.insert(
+                    username.to_string(),
+                    Collaborator {
+                        username: username.to_string(),
+                        role,
+                        created_at: unix_now(),
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_collaborator_role(
+        &self,
+        wiki: &str,
+        username: &str,
+    ) -> Result<Option<Role>, StoreError> {
+        Ok(self
+            .inner()?
+            .collaborators
+            .get(wiki)
+            .and_then(|c| c.get(username))
+            .map(|c| c.role))
+    }
+
+    async fn list_collaborators(&self, wiki: &str) -> Result<Vec<Collaborator>, StoreError> {
+        Ok(self
+            .inner()?
+            .collaborators
+            .get(wiki)
+            .map(|c| c.values().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn delete_collaborator(&self, wiki: &str, username: &str) -> Result<(), StoreError> {
+        match self
+            .inner()?
+            .collaborators
+            .get_mut(wiki)
+            .and_then(|c| c.remove(username))
+        {
+            Some(_) => Ok(()),
+            None => Err(StoreError::NotFound),
+        }
+    }
+
+    async fn list_audit_events(&self, username: &str) -> Result<Vec<AuditEvent>, StoreError> {
+        let inner = self.inner()?;
+        let mut events = inner.audit_log.get(username).cloned().unwrap_or_default();
+        events.reverse();
+        Ok(events)
+    }
+
+    async fn search(
+        &self,
+        terms: &[String],
+        username: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, StoreError> {
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+        let inner = self.inner()?;
+        let is_public = |user: &String| {
+            inner
+                .wikis
+                .get(user)
+                .is_some_and(|w| w.visibility == Visibility::Public)
+        };
+        let homes = inner.wikis.iter().map(|(user, wiki)| {
+            let text = wiki.markdown.as_deref().unwrap_or(&wiki.content);
+            (user, "", text)
+        });
+        let pages = inner.pages.iter().flat_map(|(user, pages)| {
+            pages
+                .values()
+                .map(move |p| (user, p.path.as_str(), p.markdown.as_str()))
+        });
+        let mut hits: Vec<SearchHit> = homes
+            .chain(pages)
+            .filter(|(user, _, _)| username.is_none_or(|u| u == user.as_str()))
+            .filter(|(user, _, _)| is_public(user))
+            .filter_map(|(user, page, text)| {
+                let (snippet, score) = match_text(text, terms)?;
+                Some(SearchHit {
+                    username: user.clone(),
+                    page: page.to_string(),
+                    snippet,
+                    score,
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit);
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_text() {
+        let (snippet, score) =
+            match_text("Rust is fun, rust is fast", &["RUST".to_string()]).unwrap();
+        assert_eq!(score, 2.0);
+        assert_eq!(
+            snippet,
+            format!(
+                "{}Rust{} is fun, {}rust{} is fast",
+                MATCH_START, MATCH_END, MATCH_START, MATCH_END
+            )
+        );
+        assert_eq!(
+            match_text("Rust is fun", &["rust".to_string(), "go".to_string()]),
+            None
+        );
+        let long = format!("{}needle{}", "é".repeat(100), "ü".repeat(100));
+        let (snippet, _) = match_text(&long, &["needle".to_string()]).unwrap();
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_crud() {
+        let store = MemoryStore::new();
+        store
+            .insert_wiki("test_user", "# hello", "<h1>hello</h1>", "hash")
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_wiki("test_user").await.unwrap(),
+            Some(Wiki::new(
+                "<h1>hello</h1>".to_string(),
+                Some("# hello".to_string()),
+                "hash".to_string()
+            ))
+        );
+        store
+            .update_wiki(
+                "test_user",
+                "# hi!",
+                "<h1>hi!</h1>",
+                Some("greet"),
+                "editor_user",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_wiki("test_user").await.unwrap().unwrap().content,
+            "<h1>hi!</h1>"
+        );
+        let revisions = store.list_revisions("test_user").await.unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].markdown, "# hi!");
+        assert_eq!(revisions[0].summary, Some("greet".to_string()));
+        assert_eq!(revisions[0].author, Some("editor_user".to_string()));
+        assert_eq!(
+            store
+                .get_revision("test_user", revisions[1].id)
+                .await
+                .unwrap(),
+            Some(revisions[1].clone())
+        );
+        store
+            .insert_page(
+                "test_user",
+                "guides/setup",
+                "# setup",
+                "<h1>setup</h1>",
+                None,
+                "test_user",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .insert_page(
+                    "test_user",
+                    "guides/setup",
+                    "# again",
+                    "<h1>again</h1>",
+                    None,
+                    "test_user",
+                )
+                .await,
+            Err(StoreError::AlreadyExists)
+        );
+        store
+            .update_page(
+                "test_user",
+                "guides/setup",
+                "# set up",
+                "<h1>set up</h1>",
+                None,
+                "test_user",
+            )
+            .await
+            .unwrap();
+        let page = store
+            .get_page("test_user", "guides/setup")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(page.markdown, "# set up");
+        assert_eq!(store.list_pages("test_user").await.unwrap(), vec![page]);
+        let revisions = store.list_revisions("test_user").await.unwrap();
+        assert_eq!(revisions.len(), 4);
+        assert_eq!(revisions[0].page, "guides/setup");
+        store
+            .delete_page("test_user", "guides/setup")
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_page("test_user", "guides/setup").await.unwrap(),
+            None
+        );
+        assert_eq!(store.list_revisions("test_user").await.unwrap().len(), 4);
+        let key = store
+            .create_api_key("test_user", "ci", "digest", &["write".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .create_api_key("test_user", "ci", "other", &["write".to_string()])
+                .await,
+            Err(StoreError::AlreadyExists)
+        );
+        store.touch_api_key(key.id, 42).await.unwrap();
+        assert_eq!(
+            store
+                .get_api_key("digest")
+                .await
+                .unwrap()
+                .unwrap()
+                .last_used_at,
+            Some(42)
+        );
+        assert_eq!(
+            store.delete_api_key("other_user", key.id).await,
+            Err(StoreError::NotFound)
+        );
+        store
+            .change_password("test_user", "new_hash", "password_changed")
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_wiki("test_user").await.unwrap().unwrap().password,
+            "new_hash"
+        );
+        assert_eq!(store.get_api_key("digest").await.unwrap(), None);
+        let events = store.list_audit_events("test_user").await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "password_changed");
+        store
+            .replace_recovery_codes("test_user", &["a".to_string(), "b".to_string()])
+            .await
+            .unwrap();
+        store
+            .redeem_recovery_code("test_user", "a", "newer_hash", "password_recovered")
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .redeem_recovery_code("test_user", "a", "newer_hash", "password_recovered")
+                .await,
+            Err(StoreError::NotFound)
+        );
+        assert_eq!(store.count_recovery_codes("test_user").await.unwrap(), 1);
+        assert!(!store.has_recovery_code("test_user", "a").await.unwrap());
+        assert!(store.has_recovery_code("test_user", "b").await.unwrap());
+        store.delete_wiki("test_user").await.unwrap();
+        assert_eq!(store.list_api_keys("test_user").await.unwrap(), vec![]);
+        assert_eq!(store.list_audit_events("test_user").await.unwrap(), vec![]);
+        assert_eq!(store.count_recovery_codes("test_user").await.unwrap(), 0);
+        assert_eq!(store.get_wiki("test_user").await.unwrap(), None);
+        assert_eq!(store.list_revisions("test_user").await.unwrap(), vec![]);
+        assert_eq!(
+            store.delete_wiki("test_user").await,
+            Err(StoreError::NotFound)
+        );
+    }
+}
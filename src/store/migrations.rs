@@ -0,0 +1,199 @@
+//! Versioned schema migrations for the libSQL backend.
+//!
+//! Every migration is embedded in the binary and applied at most once, in
+//! order, inside its own transaction. Applied versions are recorded in the
+//! `schema_migrations` table.
+
+use super::{unix_now, StoreError};
+use libsql::{params, Connection};
+use tracing::info;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// All known migrations, ordered by version. Never edit a migration that has
+/// already shipped: add a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_wikis",
+        sql: include_str!("../../migrations/0001_create_wikis.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "add_wiki_markdown",
+        sql: include_str!("../../migrations/0002_add_wiki_markdown.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "create_revisions",
+        sql: include_str!("../../migrations/0003_create_revisions.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "create_pages",
+        sql: include_str!("../../migrations/0004_create_pages.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "unique_wiki_users",
+        sql: include_str!("../../migrations/0005_unique_wiki_users.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "create_search_index",
+        sql: include_str!("../../migrations/0006_create_search_index.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "create_sessions",
+        sql: include_str!("../../migrations/0007_create_sessions.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "create_api_keys",
+        sql: include_str!("../../migrations/0008_create_api_keys.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "create_audit_log",
+        sql: include_str!("../../migrations/0009_create_audit_log.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "create_recovery_codes",
+        sql: include_str!("../../migrations/0010_create_recovery_codes.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "create_login_throttles",
+        sql: include_str!("../../migrations/0011_create_login_throttles.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "create_totp",
+        sql: include_str!("../../migrations/0012_create_totp.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "add_wiki_visibility",
+        sql: include_str!("../../migrations/0013_add_wiki_visibility.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "create_share_links",
+        sql: include_str!("../../migrations/0014_create_share_links.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "create_collaborators",
+        sql: include_str!("../../migrations/0015_create_collaborators.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "add_revision_author",
+        sql: include_str!("../../migrations/0016_add_revision_author.sql"),
+    },
+    Migration {
+        version: 17,
+        name: "add_wiki_user_key",
+        sql: include_str!("../../migrations/0017_add_wiki_user_key.sql"),
+    },
+    Migration {
+        version: 18,
+        name: "add_wiki_render_settings",
+        sql: include_str!("../../migrations/0018_add_wiki_render_settings.sql"),
+    },
+    Migration {
+        version: 19,
+        name: "add_wiki_user_key_conflict",
+        sql: include_str!("../../migrations/0019_add_wiki_user_key_conflict.sql"),
+    },
+];
+
+async fn applied_versions(conn: &Connection) -> Result<Vec<i64>, StoreError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER PRIMARY KEY, name TEXT NOT NULL, applied_at INTEGER NOT NULL)",
+        (),
+    )
+    .await?;
+    let mut rows = conn
+        .query("SELECT version FROM schema_migrations ORDER BY version", ())
+        .await?;
+    let mut versions = Vec::new();
+    while let Some(row) = rows.next().await? {
+        versions.push(row.get::<i64>(0)?);
+    }
+    Ok(versions)
+}
+
+/// Applies every pending migration and returns how many were applied.
+pub async fn run_migrations(conn: &Connection) -> Result<usize, StoreError> {
+    let applied = applied_versions(conn).await?;
+    let latest = MIGRATIONS.last().map(|m| m.version).unwrap_or(0);
+    if let Some(newest) = applied.iter().max() {
+        if *newest > latest {
+            return Err(StoreError::Backend(format!(
+                "Database schema version {} is newer than the latest known migration {}",
+                newest, latest
+            )));
+        }
+    }
+    let mut count = 0;
+    for migration in MIGRATIONS {
+        if applied.contains(&migration.version) {
+            continue;
+        }
+        let applied_at = unix_now();
+        let tx = conn.transaction().await?;
+        tx.execute_batch(migration.sql).await.map_err(|e| {
+            StoreError::Backend(format!(
+                "Migration {} ({}) failed: {}",
+                migration.version, migration.name, e
+            ))
+        })?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+            params![migration.version, migration.name, applied_at],
+        )
+        .await?;
+        tx.commit().await?;
+        info!(
+            event = "Migration",
+            version = migration.version,
+            "Applied migration {}",
+            migration.name
+        );
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libsql::Builder;
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        for pair in MIGRATIONS.windows(2) {
+            assert!(pair[0].version < pair[1].version);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_migrations_once() {
+        let db = Builder::new_local(":memory:").build().await.unwrap();
+        let conn = db.connect().unwrap();
+        assert_eq!(run_migrations(&conn).await, Ok(MIGRATIONS.len()));
+        assert_eq!(run_migrations(&conn).await, Ok(0));
+        let versions = applied_versions(&conn).await.unwrap();
+        assert_eq!(
+            versions,
+            MIGRATIONS.iter().map(|m| m.version).collect::<Vec<_>>()
+        );
+    }
+}
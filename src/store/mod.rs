@@ -0,0 +1,592 @@
+//! Persistence layer for wikis.
+//!
+//! Handlers only talk to the [`WikiStore`] trait, so the libSQL backend can be
+//! swapped for the in-memory one in tests and ephemeral demos.
+
+mod database;
+mod duplicates;
+mod memory;
+mod migrations;
+
+pub use database::{DatabaseConfig, LibsqlStore};
+pub use duplicates::{find_duplicate_users, resolve_duplicate_users};
+pub use memory::MemoryStore;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A [`MemoryStore`] with a wiki for each of `usernames`, all with the
+/// password `test_password`.
+#[cfg(test)]
+pub async fn test_store(usernames: &[&str]) -> MemoryStore {
+    let hashed = crate::hashing::hash_password("test_password")
+        .await
+        .unwrap();
+    let store = MemoryStore::new();
+    for username in usernames {
+        store
+            .insert_wiki(username, "# hello", "<h1>hello</h1>", &hashed)
+            .await
+            .unwrap();
+    }
+    store
+}
+
+/// Current time as seconds since the Unix epoch.
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Who can read a wiki.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Anyone, and it shows up in search.
+    #[default]
+    Public,
+    /// Anyone who knows the URL, but it never shows up in search.
+    Unlisted,
+    /// Only the owner and holders of a share link.
+    Private,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Public => "public",
+            Self::Unlisted => "unlisted",
+            Self::Private => "private",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "public" => Some(Self::Public),
+            "unlisted" => Some(Self::Unlisted),
+            "private" => Some(Self::Private),
+            _ => None,
+        }
+    }
+}
+
+/// Markdown constructs enabled on top of CommonMark when rendering a wiki.
+/// Missing fields of a request take their default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    /// GitHub flavored Markdown: tables, task lists, strikethrough, autolinks
+    /// and footnotes.
+    pub gfm: bool,
+    /// `$inline$` and `$$display$$` math.
+    pub math: bool,
+    /// A leading YAML or TOML frontmatter block, left out of the page.
+    pub frontmatter: bool,
+    /// MDX syntax. JSX and expressions are parsed but not rendered, and raw
+    /// HTML is no longer recognized.
+    pub mdx: bool,
+    /// Raw HTML in the Markdown, passed through [`crate::sanitize`] instead of
+    /// being escaped.
+    pub raw_html: bool,
+    /// A table of contents at the top of every page, unless a `[TOC]` marker
+    /// puts it elsewhere.
+    pub toc: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            gfm: true,
+            math: false,
+            frontmatter: false,
+            mdx: false,
+            raw_html: false,
+            toc: false,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// The names of the enabled constructs, as stored.
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            ("gfm", self.gfm),
+            ("math", self.math),
+            ("frontmatter", self.frontmatter),
+            ("mdx", self.mdx),
+            ("html", self.raw_html),
+            ("toc", self.toc),
+        ]
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect()
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut settings = Self {
+            gfm: false,
+            math: false,
+            frontmatter: false,
+            mdx: false,
+            raw_html: false,
+            toc: false,
+        };
+        for name in value.split(',').filter(|n| !n.is_empty()) {
+            match name {
+                "gfm" => settings.gfm = true,
+                "math" => settings.math = true,
+                "frontmatter" => settings.frontmatter = true,
+                "mdx" => settings.mdx = true,
+                "html" => settings.raw_html = true,
+                "toc" => settings.toc = true,
+                _ => return None,
+            }
+        }
+        Some(settings)
+    }
+}
+
+/// What a collaborator may do on a wiki besides reading it. The owner can do
+/// everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Create, update and restore pages, and read the Markdown source.
+    Editor,
+    /// Everything an editor can do, and delete pages.
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Editor => "editor",
+            Self::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "editor" => Some(Self::Editor),
+            "admin" => Some(Self::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wiki {
+    /// Rendered HTML, cached from `markdown`.
+    pub content: String,
+    /// The Markdown source, missing for wikis created before it was stored.
+    pub markdown: Option<String>,
+    pub password: String,
+    pub visibility: Visibility,
+    pub render_settings: RenderSettings,
+}
+
+impl Wiki {
+    /// A public wiki rendered with the default settings.
+    pub fn new(content: String, markdown: Option<String>, password: String) -> Self {
+        Self {
+            content,
+            markdown,
+            password,
+            visibility: Visibility::default(),
+            render_settings: RenderSettings::default(),
+        }
+    }
+}
+
+/// A page of a wiki other than its home page, addressed by a normalized path
+/// such as `guides/setup`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub path: String,
+    /// Rendered HTML, cached from `markdown`.
+    pub content: String,
+    pub markdown: String,
+    /// Seconds since the Unix epoch.
+    pub updated_at: i64,
+}
+
+/// A saved version of a wiki page. Every create, update and restore appends one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Revision {
+    pub id: i64,
+    /// Path of the page, empty for the home page.
+    pub page: String,
+    pub markdown: String,
+    pub summary: Option<String>,
+    /// The account that saved the revision, missing for revisions saved
+    /// before collaborators existed (by the owner).
+    pub author: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub created_at: i64,
+}
+
+/// An account allowed to edit a wiki it does not own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collaborator {
+    pub username: String,
+    pub role: Role,
+    /// Seconds since the Unix epoch.
+    pub created_at: i64,
+}
+
+/// A login session, looked up by the digest of its token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub username: String,
+    /// Seconds since the Unix epoch.
+    pub expires_at: i64,
+}
+
+/// A named API key of a wiki owner, looked up by the digest of its secret.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKey {
+    pub id: i64,
+    pub username: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Seconds since the Unix epoch.
+    pub created_at: i64,
+    /// Seconds since the Unix epoch, missing until the key is first used.
+    pub last_used_at: Option<i64>,
+}
+
+/// A revocable link to a private wiki, looked up by the digest of its token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShareLink {
+    pub id: i64,
+    pub username: String,
+    pub name: String,
+    /// Seconds since the Unix epoch.
+    pub created_at: i64,
+}
+
+/// An entry of the audit log of a wiki, such as a password change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub event: String,
+    /// Seconds since the Unix epoch.
+    pub created_at: i64,
+}
+
+/// Consecutive failed verifications of an account.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginThrottle {
+    pub failures: u32,
+    /// Seconds since the Unix epoch until which the account is locked.
+    pub locked_until: Option<i64>,
+}
+
+/// Two-factor authentication settings of a wiki owner.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Totp {
+    /// Base32 encoded shared secret.
+    pub secret: String,
+    /// False until the owner confirms enrollment with a first code.
+    pub enabled: bool,
+    /// Time step of the last accepted code.
+    pub last_step: Option<i64>,
+}
+
+/// Marks the start of a matched term in [`SearchHit::snippet`].
+pub const MATCH_START: char = '\u{E000}';
+/// Marks the end of a matched term in [`SearchHit::snippet`].
+pub const MATCH_END: char = '\u{E001}';
+
+/// A page matching a full-text search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub username: String,
+    /// Path of the page, empty for the home page.
+    pub page: String,
+    /// Plain text excerpt around the match, with matched terms wrapped in
+    /// [`MATCH_START`] and [`MATCH_END`]. It is not escaped.
+    pub snippet: String,
+    /// Relevance of the hit, higher is better.
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreError {
+    /// The wiki or page the operation refers to does not exist.
+    NotFound,
+    /// A record with the same key already exists.
+    AlreadyExists,
+    /// The underlying backend failed.
+    Backend(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "Not found"),
+            Self::AlreadyExists => write!(f, "Already exists"),
+            Self::Backend(e) => write!(f, "Storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<libsql::Error> for StoreError {
+    fn from(e: libsql::Error) -> Self {
+        Self::Backend(e.to_string())
+    }
+}
+
+/// Storage operations needed by the wiki handlers.
+#[async_trait]
+pub trait WikiStore: Send + Sync {
+    async fn get_wiki(&self, username: &str) -> Result<Option<Wiki>, StoreError>;
+
+    /// The username whose [`crate::usernames::username_key`] is `key`, if any.
+    async fn find_username_by_key(&self, key: &str) -> Result<Option<String>, StoreError>;
+
+    /// Creates a wiki together with its first revision. Fails with
+    /// [`StoreError::AlreadyExists`] when another wiki has the same
+    /// [`crate::usernames::username_key`].
+    async fn insert_wiki(
+        &self,
+        username: &str,
+        markdown: &str,
+        content: &str,
+        password: &str,
+    ) -> Result<(), StoreError>;
+
+    /// Replaces the head of a wiki and appends a revision for it, attributed
+    /// to the account `author`.
+    async fn update_wiki(
+        &self,
+        username: &str,
+        markdown: &str,
+        content: &str,
+        summary: Option<&str>,
+        author: &str,
+    ) -> Result<(), StoreError>;
+
+    /// Deletes a wiki, its pages, its credentials, its audit log, its whole
+    /// history and its collaborators, as well as the roles of its owner on
+    /// other wikis.
+    async fn delete_wiki(&self, username: &str) -> Result<(), StoreError>;
+
+    async fn get_page(&self, username: &str, path: &str) -> Result<Option<Page>, StoreError>;
+
+    /// Lists the pages of a wiki, ordered by path.
+    async fn list_pages(&self, username: &str) -> Result<Vec<Page>, StoreError>;
+
+    /// Creates a page together with its first revision. Fails with
+    /// [`StoreError::AlreadyExists`] if the path is taken.
+    async fn insert_page(
+        &self,
+        username: &str,
+        path: &str,
+        markdown: &str,
+        content: &str,
+        summary: Option<&str>,
+        author: &str,
+    ) -> Result<(), StoreError>;
+
+    /// Replaces a page and appends a revision for it.
+    async fn update_page(
+        &self,
+        username: &str,
+        path: &str,
+        markdown: &str,
+        content: &str,
+        summary: Option<&str>,
+        author: &str,
+    ) -> Result<(), StoreError>;
+
+    /// Deletes a page. Its revisions are kept so that it can be restored.
+    async fn delete_page(&self, username: &str, path: &str) -> Result<(), StoreError>;
+
+    /// Lists the revisions of every page of a wiki, newest first.
+    async fn list_revisions(&self, username: &str) -> Result<Vec<Revision>, StoreError>;
+
+    async fn get_revision(&self, username: &str, id: i64) -> Result<Option<Revision>, StoreError>;
+
+    async fn create_session(
+        &self,
+        username: &str,
+        token_hash: &str,
+        expires_at: i64,
+    ) -> Result<(), StoreError>;
+
+    async fn get_session(&self, token_hash: &str) -> Result<Option<Session>, StoreError>;
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), StoreError>;
+
+    /// Stores a new API key. Fails with [`StoreError::AlreadyExists`] if the
+    /// owner already has a key with the same name.
+    async fn create_api_key(
+        &self,
+        username: &str,
+        name: &str,
+        key_hash: &str,
+        scopes: &[String],
+    ) -> Result<ApiKey, StoreError>;
+
+    /// Lists the API keys of a wiki owner, oldest first.
+    async fn list_api_keys(&self, username: &str) -> Result<Vec<ApiKey>, StoreError>;
+
+    async fn get_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, StoreError>;
+
+    /// Records that an API key was used at `at`.
+    async fn touch_api_key(&self, id: i64, at: i64) -> Result<(), StoreError>;
+
+    async fn delete_api_key(&self, username: &str, id: i64) -> Result<(), StoreError>;
+
+    /// Replaces the password hash of a wiki, revokes all of its sessions and
+    /// API keys and records `event` in its audit log, atomically.
+    async fn change_password(
+        &self,
+        username: &str,
+        password_hash: &str,
+        event: &str,
+    ) -> Result<(), StoreError>;
+
+    /// Replaces the password hash of a wiki with an equivalent one, such as a
+    /// stronger hash of the same password. Nothing happens if the hash is no
+    /// longer `old_hash`, e.g. because the password changed in the meantime.
+    async fn rehash_password(
+        &self,
+        username: &str,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> Result<(), StoreError>;
+
+    /// Replaces every recovery code of a wiki with `code_hashes`.
+    async fn replace_recovery_codes(
+        &self,
+        username: &str,
+        code_hashes: &[String],
+    ) -> Result<(), StoreError>;
+
+    /// Number of recovery codes of a wiki that have not been redeemed yet.
+    async fn count_recovery_codes(&self, username: &str) -> Result<usize, StoreError>;
+
+    /// Whether a recovery code belongs to a wiki and has not been redeemed yet.
+    async fn has_recovery_code(&self, username: &str, code_hash: &str) -> Result<bool, StoreError>;
+
+    /// Consumes a recovery code and then behaves like
+    /// [`WikiStore::change_password`], atomically. Fails with
+    /// [`StoreError::NotFound`] if the code does not belong to the wiki.
+    async fn redeem_recovery_code(
+        &self,
+        username: &str,
+        code_hash: &str,
+        password_hash: &str,
+        event: &str,
+    ) -> Result<(), StoreError>;
+
+    async fn get_login_throttle(&self, username: &str)
+        -> Result<Option<LoginThrottle>, StoreError>;
+
+    /// Counts one more failed verification and returns the new total.
+    async fn record_login_failure(&self, username: &str) -> Result<u32, StoreError>;
+
+    async fn lock_login(&self, username: &str, until: i64) -> Result<(), StoreError>;
+
+    /// Forgets the failed verifications of an account.
+    async fn reset_login_failures(&self, username: &str) -> Result<(), StoreError>;
+
+    /// Consumes a recovery code without changing anything else. Fails with
+    /// [`StoreError::NotFound`] if the code does not belong to the wiki.
+    async fn consume_recovery_code(
+        &self,
+        username: &str,
+        code_hash: &str,
+    ) -> Result<(), StoreError>;
+
+    async fn get_totp(&self, username: &str) -> Result<Option<Totp>, StoreError>;
+
+    /// Stores a new, not yet enabled, secret, replacing any previous one.
+    async fn set_totp_secret(&self, username: &str, secret: &str) -> Result<(), StoreError>;
+
+    async fn enable_totp(&self, username: &str) -> Result<(), StoreError>;
+
+    /// Records that the code of time step `step` was accepted. Returns false,
+    /// recording nothing, if a code of the same or a later step was accepted
+    /// before, so that codes cannot be replayed.
+    async fn use_totp_step(&self, username: &str, step: i64) -> Result<bool, StoreError>;
+
+    async fn delete_totp(&self, username: &str) -> Result<(), StoreError>;
+
+    /// Appends `event` to the audit log of a wiki.
+    async fn record_audit_event(&self, username: &str, event: &str) -> Result<(), StoreError>;
+
+    async fn set_visibility(
+        &self,
+        username: &str,
+        visibility: Visibility,
+    ) -> Result<(), StoreError>;
+
+    /// Changes how a wiki is rendered, together with its cached HTML: `content`
+    /// for the home page and `pages` as (path, HTML) pairs. No revision is
+    /// recorded since the Markdown stays the same.
+    async fn set_render_settings(
+        &self,
+        username: &str,
+        settings: RenderSettings,
+        content: &str,
+        pages: &[(String, String)],
+    ) -> Result<(), StoreError>;
+
+    /// Stores a share link. Fails with [`StoreError::AlreadyExists`] if the
+    /// wiki already has a link with that name.
+    async fn create_share_link(
+        &self,
+        username: &str,
+        name: &str,
+        token_hash: &str,
+    ) -> Result<ShareLink, StoreError>;
+
+    /// Lists the share links of a wiki, oldest first.
+    async fn list_share_links(&self, username: &str) -> Result<Vec<ShareLink>, StoreError>;
+
+    async fn get_share_link(&self, token_hash: &str) -> Result<Option<ShareLink>, StoreError>;
+
+    async fn delete_share_link(&self, username: &str, id: i64) -> Result<(), StoreError>;
+
+    /// Grants `role` on the wiki `wiki` to the account `username`, replacing
+    /// any role it had.
+    async fn set_collaborator(
+        &self,
+        wiki: &str,
+        username: &str,
+        role: Role,
+    ) -> Result<(), StoreError>;
+
+    async fn get_collaborator_role(
+        &self,
+        wiki: &str,
+        username: &str,
+    ) -> Result<Option<Role>, StoreError>;
+
+    /// Lists the collaborators of a wiki, by username.
+    async fn list_collaborators(&self, wiki: &str) -> Result<Vec<Collaborator>, StoreError>;
+
+    async fn delete_collaborator(&self, wiki: &str, username: &str) -> Result<(), StoreError>;
+
+    /// Lists the audit log of a wiki, newest first.
+    async fn list_audit_events(&self, username: &str) -> Result<Vec<AuditEvent>, StoreError>;
+
+    /// Full-text search over every page of public wikis, optionally restricted
+    /// to the wiki of `username`, best matches first. `terms` are plain words, all of which
+    /// must match.
+    async fn search(
+        &self,
+        terms: &[String],
+        username: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, StoreError>;
+}
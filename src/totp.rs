@@ -0,0 +1,518 @@
+//! Optional two-factor authentication with time-based one-time passwords
+//! (RFC 6238), as generated by any authenticator app.
+//!
+//! Enrollment hands out a secret once, as an `otpauth://` URI to scan, and
+//! only takes effect after the owner confirms it with a first code. From then
+//! on, logging in and changing the password need a code too (see
+//! [`crate::auth`]). Two-factor authentication is disabled again with a code or
+//! one of the recovery codes.
+
+use crate::auth::{
+    authenticate_first_factor, authenticate_owner, check_throttle, record_failure, AuthToken,
+    Credential,
+};
+use crate::recovery::hash_code;
+use crate::store::{unix_now, StoreError, Totp, WikiStore};
+use crate::{AppState, DeleteWikiResponse};
+use axum::{extract::State, response::Json};
+use derivative::Derivative;
+use hmac::{Hmac, KeyInit, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use tracing::{error, info, instrument};
+
+type HmacSha1 = Hmac<Sha1>;
+
+/// Seconds each code is valid for.
+const STEP_SECS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Codes of this many steps before or after the current one are accepted too,
+/// to make up for clock drift and typing time.
+const SKEW_STEPS: i64 = 1;
+const SECRET_BYTES: usize = 20;
+const ISSUER: &str = "Personal Wiki";
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+/// Audit log events recorded when the owner enables or disables two-factor
+/// authentication.
+pub const TOTP_ENABLED: &str = "totp_enabled";
+pub const TOTP_DISABLED: &str = "totp_disabled";
+
+/// Unpadded RFC 4648 base32, the encoding authenticator apps expect secrets in.
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+fn generate_secret() -> String {
+    let mut bytes = [0u8; SECRET_BYTES];
+    rand::fill(&mut bytes);
+    base32_encode(&bytes)
+}
+
+/// The HOTP value (RFC 4226) of `key` for `counter`, truncated to [`DIGITS`].
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = HmacSha1::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    value % 10u32.pow(DIGITS)
+}
+
+fn code_at(key: &[u8], step: i64) -> String {
+    format!(
+        "{:0width$}",
+        hotp(key, step as u64),
+        width = DIGITS as usize
+    )
+}
+
+/// Returns the time step whose code is `code`, looking around the step of
+/// `now` (seconds since the Unix epoch).
+fn matching_step(key: &[u8], code: &str, now: i64) -> Option<i64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let current = now / STEP_SECS;
+    (current - SKEW_STEPS..=current + SKEW_STEPS).find(|step| code_at(key, *step) == code)
+}
+
+/// Checks `code` against the secret of `username`, using it up: a code is
+/// accepted at most once, as are the codes of earlier steps.
+pub async fn accept_code(
+    store: &dyn WikiStore,
+    username: &str,
+    totp: &Totp,
+    code: &str,
+) -> Result<bool, String> {
+    let key = base32_decode(&totp.secret)
+        .ok_or_else(|| "The two-factor authentication secret is corrupted".to_string())?;
+    match matching_step(&key, code, unix_now()) {
+        Some(step) => store
+            .use_totp_step(username, step)
+            .await
+            .map_err(|e| e.to_string()),
+        None => Ok(false),
+    }
+}
+
+/// Escapes everything but unreserved characters (RFC 3986).
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// The `otpauth://` URI authenticator apps import secrets from, usually as a
+/// QR code.
+fn otpauth_uri(username: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{username}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECS}",
+        issuer = percent_encode(ISSUER),
+        username = percent_encode(username),
+    )
+}
+
+/// Starts enrolling `username`, replacing any unconfirmed secret. Returns the
+/// new secret and its `otpauth://` URI, which are not shown again.
+pub async fn begin_enrollment(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+) -> Result<(String, String), String> {
+    authenticate_owner(store, username, credential).await?;
+    match store.get_totp(username).await {
+        Ok(Some(totp)) if totp.enabled => {
+            return Err("Two-factor authentication is already enabled".to_string())
+        }
+        Ok(_) => {}
+        Err(e) => return Err(e.to_string()),
+    }
+    let secret = generate_secret();
+    store
+        .set_totp_secret(username, &secret)
+        .await
+        .map_err(|e| e.to_string())?;
+    let uri = otpauth_uri(username, &secret);
+    Ok((secret, uri))
+}
+
+/// Enables two-factor authentication once the owner proves, with a first
+/// code, that their app holds the secret.
+pub async fn confirm_enrollment(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    code: &str,
+) -> Option<String> {
+    if let Err(e) = authenticate_owner(store, username, credential).await {
+        return Some(e);
+    }
+    let totp = match store.get_totp(username).await {
+        Ok(Some(t)) if t.enabled => {
+            return Some("Two-factor authentication is already enabled".to_string())
+        }
+        Ok(Some(t)) => t,
+        Ok(None) => return Some("Start the enrollment first".to_string()),
+        Err(e) => return Some(e.to_string()),
+    };
+    match accept_code(store, username, &totp, code).await {
+        Ok(true) => {}
+        Ok(false) => return Some("Wrong two-factor authentication code".to_string()),
+        Err(e) => return Some(e),
+    }
+    if let Err(e) = store.enable_totp(username).await {
+        return Some(e.to_string());
+    }
+    store
+        .record_audit_event(username, TOTP_ENABLED)
+        .await
+        .err()
+        .map(|e| e.to_string())
+}
+
+/// Disables two-factor authentication with the password or a session, plus
+/// either a current code or a recovery code, which is used up. Wrong codes
+/// count towards the lockout of the account.
+pub async fn disable(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    code: &str,
+) -> Option<String> {
+    if let Err(e) = authenticate_first_factor(store, username, credential).await {
+        return Some(e);
+    }
+    let totp = match store.get_totp(username).await {
+        Ok(Some(t)) if t.enabled => t,
+        Ok(_) => return Some("Two-factor authentication is not enabled".to_string()),
+        Err(e) => return Some(e.to_string()),
+    };
+    if let Err(e) = check_throttle(store, username).await {
+        return Some(e);
+    }
+    let accepted = match accept_code(store, username, &totp, code).await {
+        Ok(true) => true,
+        Ok(false) => match store
+            .consume_recovery_code(username, &hash_code(code))
+            .await
+        {
+            Ok(()) => true,
+            Err(StoreError::NotFound) => false,
+            Err(e) => return Some(e.to_string()),
+        },
+        Err(e) => return Some(e),
+    };
+    if !accepted {
+        return Some(
+            record_failure(store, username)
+                .await
+                .err()
+                .unwrap_or_else(|| "Wrong two-factor authentication or recovery code".to_string()),
+        );
+    }
+    if let Err(e) = store.delete_totp(username).await {
+        return Some(e.to_string());
+    }
+    store
+        .record_audit_event(username, TOTP_DISABLED)
+        .await
+        .err()
+        .map(|e| e.to_string())
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct EnrollTotpRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Derivative)]
+#[derivative(Debug)]
+pub struct EnrollTotpResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    #[derivative(Debug = "ignore")]
+    pub secret: Option<String>,
+    #[derivative(Debug = "ignore")]
+    pub otpauth_uri: Option<String>,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct TotpCodeRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+    #[derivative(Debug = "ignore")]
+    pub code: String,
+}
+
+#[instrument(skip(state))]
+pub async fn enroll(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<EnrollTotpRequest>,
+) -> Json<EnrollTotpResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match begin_enrollment(state.store.as_ref(), &payload.username, credential.as_ref()).await {
+        Ok((secret, uri)) => {
+            info!(event = "EnrollTotp", data_id = %payload.username, "Two-factor authentication enrollment started");
+            Json(EnrollTotpResponse {
+                success: true,
+                error: None,
+                secret: Some(secret),
+                otpauth_uri: Some(uri),
+            })
+        }
+        Err(e) => {
+            error!(event = "EnrollTotp", data_id = %payload.username, "{}", e);
+            Json(EnrollTotpResponse {
+                success: false,
+                error: Some(e),
+                secret: None,
+                otpauth_uri: None,
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn confirm(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<TotpCodeRequest>,
+) -> Json<DeleteWikiResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match confirm_enrollment(
+        state.store.as_ref(),
+        &payload.username,
+        credential.as_ref(),
+        &payload.code,
+    )
+    .await
+    {
+        Some(e) => {
+            error!(event = "ConfirmTotp", data_id = %payload.username, "{}", e);
+            Json(DeleteWikiResponse {
+                success: false,
+                error: Some(e),
+            })
+        }
+        None => {
+            info!(event = "ConfirmTotp", data_id = %payload.username, "Two-factor authentication successfully enabled");
+            Json(DeleteWikiResponse {
+                success: true,
+                error: None,
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn disable_totp(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<TotpCodeRequest>,
+) -> Json<DeleteWikiResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match disable(
+        state.store.as_ref(),
+        &payload.username,
+        credential.as_ref(),
+        &payload.code,
+    )
+    .await
+    {
+        Some(e) => {
+            error!(event = "DisableTotp", data_id = %payload.username, "{}", e);
+            Json(DeleteWikiResponse {
+                success: false,
+                error: Some(e),
+            })
+        }
+        None => {
+            info!(event = "DisableTotp", data_id = %payload.username, "Two-factor authentication successfully disabled");
+            Json(DeleteWikiResponse {
+                success: true,
+                error: None,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{authenticate, change_password, login, Scope};
+    use crate::recovery::issue_recovery_codes;
+    use crate::store::test_store;
+
+    fn current_code(secret: &str) -> String {
+        code_at(&base32_decode(secret).unwrap(), unix_now() / STEP_SECS)
+    }
+
+    #[test]
+    fn test_base32() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI"), Some(b"foobar".to_vec()));
+        assert_eq!(base32_decode("mzxw6ytboi======"), Some(b"foobar".to_vec()));
+        assert_eq!(base32_decode("MZXW1"), None);
+        let secret = generate_secret();
+        assert_eq!(secret.len(), 32);
+        assert_eq!(base32_decode(&secret).unwrap().len(), SECRET_BYTES);
+    }
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        // the SHA-1 test vectors of RFC 6238, truncated to six digits
+        let key = b"12345678901234567890";
+        assert_eq!(code_at(key, 59 / STEP_SECS), "287082");
+        assert_eq!(code_at(key, 1111111109 / STEP_SECS), "081804");
+        assert_eq!(code_at(key, 1234567890 / STEP_SECS), "005924");
+        assert_eq!(code_at(key, 20000000000 / STEP_SECS), "353130");
+        assert_eq!(matching_step(key, "287 082", 59 + STEP_SECS), Some(1));
+        assert_eq!(matching_step(key, "287082", 59 + 2 * STEP_SECS), None);
+        assert_eq!(matching_step(key, "28708", 59), None);
+    }
+
+    #[test]
+    fn test_otpauth_uri() {
+        assert_eq!(
+            otpauth_uri("alice smith", "MZXW6YTBOI"),
+            "otpauth://totp/Personal%20Wiki:alice%20smith?secret=MZXW6YTBOI&issuer=Personal%20Wiki&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_totp_lifecycle() {
+        let store = test_store(&["test_user"]).await;
+        let recovery_codes = issue_recovery_codes(&store, "test_user").await.unwrap();
+        let password = Credential::Password("test_password".to_string());
+        let (secret, _) = begin_enrollment(&store, "test_user", Some(&password))
+            .await
+            .unwrap();
+        // nothing changes until the enrollment is confirmed
+        assert!(login(&store, "test_user", "test_password", None)
+            .await
+            .is_ok());
+        assert_eq!(
+            confirm_enrollment(&store, "test_user", Some(&password), "000000").await,
+            Some("Wrong two-factor authentication code".to_string())
+        );
+        let code = current_code(&secret);
+        assert_eq!(
+            confirm_enrollment(&store, "test_user", Some(&password), &code).await,
+            None
+        );
+        assert!(begin_enrollment(&store, "test_user", Some(&password))
+            .await
+            .is_err());
+
+        // passwords now need a code, which works only once
+        assert_eq!(
+            login(&store, "test_user", "test_password", None)
+                .await
+                .err(),
+            Some("A two-factor authentication code is required".to_string())
+        );
+        assert!(login(&store, "test_user", "test_password", Some(&code))
+            .await
+            .is_err());
+        assert!(
+            authenticate(&store, "test_user", Some(&password), Scope::Write)
+                .await
+                .is_err()
+        );
+        assert!(
+            change_password(&store, "test_user", "test_password", None, "new_password")
+                .await
+                .is_some()
+        );
+        // a session opened with a code works as before (the current code
+        // is used up, so start over with the same secret)
+        store.set_totp_secret("test_user", &secret).await.unwrap();
+        store.enable_totp("test_user").await.unwrap();
+        let (token, _) = login(
+            &store,
+            "test_user",
+            "test_password",
+            Some(&current_code(&secret)),
+        )
+        .await
+        .unwrap();
+        let session = Credential::Session(token);
+        assert!(
+            authenticate(&store, "test_user", Some(&session), Scope::Write)
+                .await
+                .is_ok()
+        );
+
+        // disabling takes a recovery code when the app is lost
+        assert_eq!(
+            disable(&store, "test_user", Some(&session), "000000").await,
+            Some("Wrong two-factor authentication or recovery code".to_string())
+        );
+        assert_eq!(
+            disable(&store, "test_user", Some(&session), &recovery_codes[0]).await,
+            None
+        );
+        assert_eq!(store.count_recovery_codes("test_user").await.unwrap(), 9);
+        assert!(login(&store, "test_user", "test_password", None)
+            .await
+            .is_ok());
+        let events: Vec<String> = store
+            .list_audit_events("test_user")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.event)
+            .collect();
+        assert_eq!(events, vec![TOTP_DISABLED, TOTP_ENABLED]);
+    }
+}
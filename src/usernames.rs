@@ -0,0 +1,252 @@
+//! Rules for the username of a new wiki, which is also its URL and the name of
+//! its owner's account.
+//!
+//! Usernames are NFKC normalized, so that e.g. fullwidth letters become their
+//! plain form, then must be 3 to 32 letters, digits, `-` or `_` from a single
+//! script. Two usernames that differ only by case or by characters that look
+//! alike (`alice`, `Alice`, `ａｌｉｃｅ`, `al1ce`) cannot both exist, and names
+//! of routes and a few others are reserved. Wikis created before these rules
+//! keep their username.
+
+use crate::store::WikiStore;
+use serde::Serialize;
+use std::fmt;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::{skeleton, MixedScript};
+
+pub const MIN_USERNAME_LENGTH: usize = 3;
+pub const MAX_USERNAME_LENGTH: usize = 32;
+
+/// Names of routes served next to the wikis, and names that would let someone
+/// pass for the site itself.
+const RESERVED_USERNAMES: &[&str] = &[
+    "about",
+    "admin",
+    "administrator",
+    "api",
+    "assets",
+    "audit",
+    "collaborators",
+    "help",
+    "keys",
+    "login",
+    "logout",
+    "pages",
+    "password",
+    "recovery",
+    "restore",
+    "revisions",
+    "root",
+    "scripts",
+    "search",
+    "settings",
+    "shares",
+    "source",
+    "static",
+    "support",
+    "system",
+    "totp",
+    "visibility",
+    "wikis",
+    "www",
+];
+
+/// Why a username was refused, serialized with a machine readable `code`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum UsernameError {
+    Length {
+        min: usize,
+        max: usize,
+    },
+    InvalidCharacter {
+        character: char,
+    },
+    InvalidStart,
+    MixedScripts,
+    Reserved,
+    Taken,
+    /// Only differs from `existing` by characters that look alike.
+    Confusable {
+        existing: String,
+    },
+}
+
+impl fmt::Display for UsernameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Length { min, max } => write!(
+                f,
+                "Usernames must be between {} and {} characters long",
+                min, max
+            ),
+            Self::InvalidCharacter { character } => write!(
+                f,
+                "Usernames can only contain letters, digits, '-' and '_', not {:?}",
+                character
+            ),
+            Self::InvalidStart => write!(f, "Usernames must start with a letter or a digit"),
+            Self::MixedScripts => write!(f, "Usernames cannot mix letters from different scripts"),
+            Self::Reserved => write!(f, "This username is reserved"),
+            Self::Taken => write!(f, "Username is already taken"),
+            Self::Confusable { existing } => write!(
+                f,
+                "This username is too similar to the existing user {}",
+                existing
+            ),
+        }
+    }
+}
+
+/// A refused field of a request, as returned next to the error message.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub field: &'static str,
+    #[serde(flatten)]
+    pub reason: UsernameError,
+    pub message: String,
+}
+
+impl From<UsernameError> for ValidationError {
+    fn from(reason: UsernameError) -> Self {
+        Self {
+            field: "username",
+            message: reason.to_string(),
+            reason,
+        }
+    }
+}
+
+/// The form two usernames share when they differ only by case or by
+/// confusable characters, as defined by Unicode TR39.
+pub fn username_key(username: &str) -> String {
+    let folded = username.nfkc().collect::<String>().to_lowercase();
+    skeleton(&folded).collect::<String>().to_lowercase()
+}
+
+/// Checks `username` against every rule but uniqueness and returns its
+/// normalized form, which is the one to store.
+pub fn normalize_username(username: &str) -> Result<String, UsernameError> {
+    let normalized: String = username.nfkc().collect();
+    let length = normalized.chars().count();
+    if !(MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&length) {
+        return Err(UsernameError::Length {
+            min: MIN_USERNAME_LENGTH,
+            max: MAX_USERNAME_LENGTH,
+        });
+    }
+    if let Some(character) = normalized
+        .chars()
+        .find(|c| !(c.is_alphanumeric() || *c == '-' || *c == '_'))
+    {
+        return Err(UsernameError::InvalidCharacter { character });
+    }
+    if !normalized.starts_with(char::is_alphanumeric) {
+        return Err(UsernameError::InvalidStart);
+    }
+    if !normalized.as_str().is_single_script() {
+        return Err(UsernameError::MixedScripts);
+    }
+    let key = username_key(&normalized);
+    if RESERVED_USERNAMES.iter().any(|r| username_key(r) == key) {
+        return Err(UsernameError::Reserved);
+    }
+    Ok(normalized)
+}
+
+/// Fails when an existing username has the same [`username_key`] as the
+/// normalized `username`.
+pub async fn check_available(
+    store: &dyn WikiStore,
+    username: &str,
+) -> Result<Option<UsernameError>, String> {
+    match store.find_username_by_key(&username_key(username)).await {
+        Ok(Some(existing)) if existing.to_lowercase() == username.to_lowercase() => {
+            Ok(Some(UsernameError::Taken))
+        }
+        Ok(Some(existing)) => Ok(Some(UsernameError::Confusable { existing })),
+        Ok(None) => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn test_normalize_username() {
+        assert_eq!(normalize_username("alice"), Ok("alice".to_string()));
+        assert_eq!(normalize_username("Bob_99"), Ok("Bob_99".to_string()));
+        assert_eq!(normalize_username("ｃａｒｏｌ"), Ok("carol".to_string()));
+        assert_eq!(normalize_username("zoë"), Ok("zoë".to_string()));
+        assert_eq!(normalize_username("дмитрий"), Ok("дмитрий".to_string()));
+        assert_eq!(
+            normalize_username("ab"),
+            Err(UsernameError::Length { min: 3, max: 32 })
+        );
+        assert_eq!(
+            normalize_username(&"a".repeat(33)),
+            Err(UsernameError::Length { min: 3, max: 32 })
+        );
+        for (username, character) in [
+            ("al ice", ' '),
+            ("../alice", '.'),
+            ("a/b/c", '/'),
+            ("<script>", '<'),
+            ("ali\u{200b}ce", '\u{200b}'),
+        ] {
+            assert_eq!(
+                normalize_username(username),
+                Err(UsernameError::InvalidCharacter { character })
+            );
+        }
+        assert_eq!(
+            normalize_username("_alice"),
+            Err(UsernameError::InvalidStart)
+        );
+        // a Cyrillic 'а' among Latin letters
+        assert_eq!(
+            normalize_username("p\u{0430}ypal"),
+            Err(UsernameError::MixedScripts)
+        );
+        for username in ["admin", "API", "ＳＣＲＩＰＴＳ", "about", "keys", "shares"] {
+            assert_eq!(normalize_username(username), Err(UsernameError::Reserved));
+        }
+    }
+
+    #[test]
+    fn test_username_key() {
+        assert_eq!(username_key("alice"), username_key("ALICE"));
+        assert_eq!(username_key("alice"), username_key("ａｌｉｃｅ"));
+        assert_eq!(username_key("bill"), username_key("bi11"));
+        assert_eq!(username_key("bill"), username_key("BILL"));
+        assert_ne!(username_key("alice"), username_key("bob"));
+    }
+
+    #[tokio::test]
+    async fn test_check_available() {
+        let store = MemoryStore::new();
+        store
+            .insert_wiki("alice", "# hello", "<h1>hello</h1>", "hash")
+            .await
+            .unwrap();
+        assert_eq!(check_available(&store, "bob").await, Ok(None));
+        assert_eq!(
+            check_available(&store, "Alice").await,
+            Ok(Some(UsernameError::Taken))
+        );
+        assert_eq!(
+            check_available(&store, "a1ice").await,
+            Ok(Some(UsernameError::Confusable {
+                existing: "alice".to_string()
+            }))
+        );
+        // the store holds the line when two creations race
+        assert!(store
+            .insert_wiki("ALICE", "# hello", "<h1>hello</h1>", "hash")
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,505 @@
+//! Who can read a wiki: everyone (public), everyone with the URL (unlisted) or
+//! only the owner, its collaborators and holders of a share link (private).
+//!
+//! The owner and collaborators prove themselves with their session cookie or
+//! an API key with the `read-source` scope. Share links carry a revocable token in their
+//! `share` query parameter, which the pages read with it pass on to their links
+//! within the wiki.
+
+use crate::auth::{
+    authenticate_owner, authorize, generate_token, hash_token, AuthToken, Credential, Scope,
+};
+use crate::store::{ShareLink, StoreError, Visibility, WikiStore};
+use crate::{escape_html, page_url, AppState, DeleteWikiResponse};
+use axum::{extract::State, response::Json};
+use derivative::Derivative;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, instrument};
+
+const MAX_SHARE_LINK_NAME_LENGTH: usize = 64;
+
+/// Query parameters of the read routes.
+#[derive(Deserialize, Derivative, Default)]
+#[derivative(Debug)]
+pub struct ReadParams {
+    /// Token of a share link, for private wikis.
+    #[derivative(Debug = "ignore")]
+    pub share: Option<String>,
+}
+
+/// Fails unless the reader may see a wiki of `username` with `visibility`.
+pub async fn check_read_access(
+    store: &dyn WikiStore,
+    username: &str,
+    visibility: Visibility,
+    token: &AuthToken,
+    share: Option<&str>,
+) -> Result<(), String> {
+    if visibility != Visibility::Private {
+        return Ok(());
+    }
+    if let Some(share) = share {
+        match store.get_share_link(&hash_token(share)).await {
+            Ok(Some(link)) if link.username == username => return Ok(()),
+            Ok(_) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    let credential = Credential::resolve(None, token);
+    if credential.is_some()
+        && authorize(
+            store,
+            username,
+            None,
+            credential.as_ref(),
+            Scope::ReadSource,
+        )
+        .await
+        .is_ok()
+    {
+        return Ok(());
+    }
+    Err(format!(
+        "The wiki of user {} is private... Please log in or use a share link to read it!",
+        username
+    ))
+}
+
+/// Like [`check_read_access`], loading the visibility of the wiki first.
+/// Missing wikis pass, so that callers report them as such.
+pub async fn authorize_read(
+    store: &dyn WikiStore,
+    username: &str,
+    token: &AuthToken,
+    share: Option<&str>,
+) -> Result<(), String> {
+    match store.get_wiki(username).await {
+        Ok(Some(wiki)) => check_read_access(store, username, wiki.visibility, token, share).await,
+        Ok(None) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Adds the `share` token a page is read with to the links of `html` to
+/// other pages of the same wiki, so that readers with a share link can follow
+/// them. Links that already have a query are left as they are.
+pub fn carry_share(html: &str, username: &str, share: Option<&str>) -> String {
+    let Some(share) = share else {
+        return html.to_string();
+    };
+    let prefix = format!("href=\"{}", page_url(username, ""));
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(&prefix) {
+        let after = &rest[start + prefix.len()..];
+        let (href, tail) = after.split_at(after.find('"').unwrap_or(after.len()));
+        out.push_str(&rest[..start + prefix.len()]);
+        let same_wiki = matches!(href.chars().next(), None | Some('/' | '#'))
+            && !href.contains("..")
+            && !href.contains('?');
+        if same_wiki {
+            let (path, fragment) = href.split_at(href.find('#').unwrap_or(href.len()));
+            out.push_str(&format!(
+                "{}?share={}{}",
+                path,
+                escape_html(share),
+                fragment
+            ));
+        } else {
+            out.push_str(href);
+        }
+        rest = tail;
+    }
+    out.push_str(rest);
+    out
+}
+
+pub async fn set_wiki_visibility(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    visibility: Visibility,
+) -> Option<String> {
+    if let Err(e) = authenticate_owner(store, username, credential).await {
+        return Some(e);
+    }
+    store
+        .set_visibility(username, visibility)
+        .await
+        .err()
+        .map(|e| e.to_string())
+}
+
+/// Creates a share link to the wiki of `username`, returning it with its
+/// token. The token is not stored and cannot be shown again.
+pub async fn mint_share_link(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    name: &str,
+) -> Result<(ShareLink, String), String> {
+    authenticate_owner(store, username, credential).await?;
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_SHARE_LINK_NAME_LENGTH {
+        return Err(format!(
+            "Share link names must be between 1 and {} characters long",
+            MAX_SHARE_LINK_NAME_LENGTH
+        ));
+    }
+    let token = generate_token();
+    match store
+        .create_share_link(username, name, &hash_token(&token))
+        .await
+    {
+        Ok(link) => Ok((link, token)),
+        Err(StoreError::AlreadyExists) => {
+            Err(format!("A share link named '{}' already exists", name))
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn list_share_links(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+) -> Result<Vec<ShareLink>, String> {
+    authenticate_owner(store, username, credential).await?;
+    store
+        .list_share_links(username)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+pub async fn revoke_share_link(
+    store: &dyn WikiStore,
+    username: &str,
+    credential: Option<&Credential>,
+    id: i64,
+) -> Option<String> {
+    if let Err(e) = authenticate_owner(store, username, credential).await {
+        return Some(e);
+    }
+    match store.delete_share_link(username, id).await {
+        Ok(()) => None,
+        Err(StoreError::NotFound) => Some(format!("Share link {} does not exist", id)),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct SetVisibilityRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+    pub visibility: Visibility,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct CreateShareLinkRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+    pub name: String,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct ListShareLinksRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Derivative)]
+#[derivative(Debug)]
+pub struct RevokeShareLinkRequest {
+    pub username: String,
+    #[derivative(Debug = "ignore")]
+    #[serde(default)]
+    pub password: Option<String>,
+    pub id: i64,
+}
+
+/// A share link as shown to its owner, without its token.
+#[derive(Serialize, Debug)]
+pub struct ShareLinkSummary {
+    pub id: i64,
+    pub name: String,
+    pub created_at: i64,
+}
+
+impl From<ShareLink> for ShareLinkSummary {
+    fn from(link: ShareLink) -> Self {
+        Self {
+            id: link.id,
+            name: link.name,
+            created_at: link.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Derivative)]
+#[derivative(Debug)]
+pub struct CreateShareLinkResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    /// The URL to hand out, carrying the token. Only returned once.
+    #[derivative(Debug = "ignore")]
+    pub url: Option<String>,
+    pub share_link: Option<ShareLinkSummary>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ListShareLinksResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    pub share_links: Vec<ShareLinkSummary>,
+}
+
+#[instrument(skip(state))]
+pub async fn set_visibility(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<SetVisibilityRequest>,
+) -> Json<DeleteWikiResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match set_wiki_visibility(
+        state.store.as_ref(),
+        &payload.username,
+        credential.as_ref(),
+        payload.visibility,
+    )
+    .await
+    {
+        Some(e) => {
+            error!(event = "SetVisibility", data_id = %payload.username, "{}", e);
+            Json(DeleteWikiResponse {
+                success: false,
+                error: Some(e),
+            })
+        }
+        None => {
+            info!(event = "SetVisibility", data_id = %payload.username, "Wiki is now {}", payload.visibility.as_str());
+            Json(DeleteWikiResponse {
+                success: true,
+                error: None,
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn create_share(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<CreateShareLinkRequest>,
+) -> Json<CreateShareLinkResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match mint_share_link(
+        state.store.as_ref(),
+        &payload.username,
+        credential.as_ref(),
+        &payload.name,
+    )
+    .await
+    {
+        Ok((link, secret)) => {
+            info!(event = "CreateShareLink", data_id = %payload.username, "Share link {} successfully created", link.id);
+            Json(CreateShareLinkResponse {
+                success: true,
+                error: None,
+                url: Some(format!("/wikis/{}?share={}", payload.username, secret)),
+                share_link: Some(link.into()),
+            })
+        }
+        Err(e) => {
+            error!(event = "CreateShareLink", data_id = %payload.username, "{}", e);
+            Json(CreateShareLinkResponse {
+                success: false,
+                error: Some(e),
+                url: None,
+                share_link: None,
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn list_shares(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<ListShareLinksRequest>,
+) -> Json<ListShareLinksResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match list_share_links(state.store.as_ref(), &payload.username, credential.as_ref()).await {
+        Ok(links) => {
+            info!(event = "ListShareLinks", data_id = %payload.username, "Share links successfully retrieved");
+            Json(ListShareLinksResponse {
+                success: true,
+                error: None,
+                share_links: links.into_iter().map(ShareLinkSummary::from).collect(),
+            })
+        }
+        Err(e) => {
+            error!(event = "ListShareLinks", data_id = %payload.username, "{}", e);
+            Json(ListShareLinksResponse {
+                success: false,
+                error: Some(e),
+                share_links: vec![],
+            })
+        }
+    }
+}
+
+#[instrument(skip(state))]
+pub async fn revoke_share(
+    State(state): State<AppState>,
+    token: AuthToken,
+    Json(payload): Json<RevokeShareLinkRequest>,
+) -> Json<DeleteWikiResponse> {
+    let credential = Credential::resolve(payload.password.as_deref(), &token);
+    match revoke_share_link(
+        state.store.as_ref(),
+        &payload.username,
+        credential.as_ref(),
+        payload.id,
+    )
+    .await
+    {
+        Some(e) => {
+            error!(event = "RevokeShareLink", data_id = %payload.username, "{}", e);
+            Json(DeleteWikiResponse {
+                success: false,
+                error: Some(e),
+            })
+        }
+        None => {
+            info!(event = "RevokeShareLink", data_id = %payload.username, "Share link {} successfully revoked", payload.id);
+            Json(DeleteWikiResponse {
+                success: true,
+                error: None,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::login;
+    use crate::store::test_store;
+
+    #[test]
+    fn test_carry_share() {
+        let html = "<a href=\"/wikis/alice\">a</a> <a href=\"/wikis/alice/guides/setup#install\">b</a> <a href=\"/wikis/alice/pages\">c</a> <a href=\"/wikis/alicia\">d</a> <a href=\"/wikis/bob/notes\">e</a> <a href=\"/wikis/alice/../bob\">f</a> <a href=\"#top\">g</a>";
+        assert_eq!(carry_share(html, "alice", None), html);
+        assert_eq!(
+            carry_share(html, "alice", Some("s3cr\"t")),
+            "<a href=\"/wikis/alice?share=s3cr&quot;t\">a</a> <a href=\"/wikis/alice/guides/setup?share=s3cr&quot;t#install\">b</a> <a href=\"/wikis/alice/pages?share=s3cr&quot;t\">c</a> <a href=\"/wikis/alicia\">d</a> <a href=\"/wikis/bob/notes\">e</a> <a href=\"/wikis/alice/../bob\">f</a> <a href=\"#top\">g</a>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_private_wiki_access() {
+        let store = test_store(&["test_user", "other_user"]).await;
+        let anonymous = AuthToken(None);
+        let password = Credential::Password("test_password".to_string());
+        assert!(authorize_read(&store, "test_user", &anonymous, None)
+            .await
+            .is_ok());
+        assert_eq!(
+            set_wiki_visibility(&store, "test_user", Some(&password), Visibility::Private).await,
+            None
+        );
+        assert!(authorize_read(&store, "test_user", &anonymous, None)
+            .await
+            .is_err());
+
+        // the owner's session
+        let (session, _) = login(&store, "test_user", "test_password", None)
+            .await
+            .unwrap();
+        assert!(
+            authorize_read(&store, "test_user", &AuthToken(Some(session)), None)
+                .await
+                .is_ok()
+        );
+        let (other_session, _) = login(&store, "other_user", "test_password", None)
+            .await
+            .unwrap();
+        assert!(
+            authorize_read(&store, "test_user", &AuthToken(Some(other_session)), None)
+                .await
+                .is_err()
+        );
+
+        // share links, only for the wiki they were made for
+        let (link, share) = mint_share_link(&store, "test_user", Some(&password), "team")
+            .await
+            .unwrap();
+        assert!(
+            authorize_read(&store, "test_user", &anonymous, Some(&share))
+                .await
+                .is_ok()
+        );
+        assert_eq!(
+            set_wiki_visibility(&store, "other_user", Some(&password), Visibility::Private).await,
+            None
+        );
+        assert!(
+            authorize_read(&store, "other_user", &anonymous, Some(&share))
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            list_share_links(&store, "test_user", Some(&password))
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            revoke_share_link(&store, "test_user", Some(&password), link.id).await,
+            None
+        );
+        assert!(
+            authorize_read(&store, "test_user", &anonymous, Some(&share))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_only_public_wikis_are_searched() {
+        let store = test_store(&["public_user", "unlisted_user", "private_user"]).await;
+        let password = Credential::Password("test_password".to_string());
+        for (username, visibility) in [
+            ("public_user", Visibility::Public),
+            ("unlisted_user", Visibility::Unlisted),
+            ("private_user", Visibility::Private),
+        ] {
+            assert_eq!(
+                set_wiki_visibility(&store, username, Some(&password), visibility).await,
+                None
+            );
+        }
+        let hits = store
+            .search(&["hello".to_string()], None, 10)
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].username, "public_user");
+        // unlisted wikis can still be read by anyone with the URL
+        assert!(
+            authorize_read(&store, "unlisted_user", &AuthToken(None), None)
+                .await
+                .is_ok()
+        );
+    }
+}
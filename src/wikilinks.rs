@@ -0,0 +1,302 @@
+//! Wiki-links between pages: `[[Page]]` links to a page of the same wiki,
+//! `[[Page|label]]` shows another label and `[[@otheruser/Page]]` links to a
+//! page of another wiki (`[[@otheruser]]` to its home page). Targets follow
+//! the same rules as page paths, so `[[Getting Started]]` links to
+//! `getting-started`.
+//!
+//! Links are made when the Markdown is rendered, outside of code and of other
+//! links, and cached with the HTML. Whether their page exists is only known
+//! when they are served: [`mark_missing_links`] styles the links to missing
+//! pages then, and those lead to a form to create the page for whoever may
+//! write to the wiki (see [`crate::pages::get_page`]).
+
+use crate::auth::AuthToken;
+use crate::page_url;
+use crate::pages::normalize_page_path;
+use crate::store::WikiStore;
+use crate::visibility::authorize_read;
+use std::collections::{HashMap, HashSet};
+
+const LINK_PREFIX: &str = "<a class=\"wikilink\" href=\"";
+const MISSING_LINK_PREFIX: &str =
+    "<a class=\"wikilink wikilink-missing\" title=\"Create this page\" href=\"";
+
+/// Elements whose text is left alone.
+const SKIPPED_ELEMENTS: &[&str] = &["a", "code", "pre"];
+
+/// The wiki and page path `target` points to from the wiki of `username`, the
+/// home page being the empty path.
+fn parse_target(target: &str, username: &str) -> Option<(String, String)> {
+    let target = target.trim();
+    let (user, path) = match target.strip_prefix('@') {
+        Some(other) => other.split_once('/').unwrap_or((other, "")),
+        None => (username, target),
+    };
+    // also keeps the usernames of wikis from before their rules out of URLs
+    if user.is_empty()
+        || !user
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        return None;
+    }
+    let path = match (target.starts_with('@'), path.trim()) {
+        (true, "") => String::new(),
+        _ => normalize_page_path(path).ok()?,
+    };
+    Some((user.to_string(), path))
+}
+
+/// Replaces the wiki-links of a run of (escaped) text.
+fn link_text(text: &str, username: &str, out: &mut String) {
+    let mut rest = text;
+    while let Some(start) = rest.find("[[") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("]]") else {
+            break;
+        };
+        let inner = &after[..end];
+        let (target, label) = inner.split_once('|').unwrap_or((inner, inner));
+        let link = if inner.contains('[') {
+            None
+        } else {
+            parse_target(target, username)
+        };
+        match link {
+            Some((user, path)) => {
+                let label = match label.trim() {
+                    "" => target.trim(),
+                    label => label,
+                };
+                out.push_str(&rest[..start]);
+                out.push_str(&format!(
+                    "{}{}\">{}</a>",
+                    LINK_PREFIX,
+                    page_url(&user, &path),
+                    label
+                ));
+                rest = &after[end + 2..];
+            }
+            // `[[[Page]]]` still links the inner `[[Page]]`
+            None => {
+                out.push_str(&rest[..start + 1]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+/// Length of the tag `html` starts with, quoted attribute values included.
+pub fn tag_length(html: &str) -> usize {
+    let mut quote = None;
+    for (i, c) in html.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return i + 1,
+            (Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    html.len()
+}
+
+/// Turns the wiki-links of rendered `html` into links, relative to the wiki of
+/// `username`.
+pub fn link_wiki_pages(html: &str, username: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    // how many skipped elements the text is in
+    let mut depth = 0usize;
+    let mut rest = html;
+    while !rest.is_empty() {
+        let (text, tail) = rest.split_at(rest.find('<').unwrap_or(rest.len()));
+        if depth == 0 {
+            link_text(text, username, &mut out);
+        } else {
+            out.push_str(text);
+        }
+        if tail.is_empty() {
+            break;
+        }
+        let (tag, next) = tail.split_at(tag_length(tail));
+        let closing = tag.starts_with("</");
+        let name: String = tag
+            .trim_start_matches(['<', '/'])
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if SKIPPED_ELEMENTS.contains(&name.as_str()) {
+            if closing {
+                depth = depth.saturating_sub(1);
+            } else {
+                depth += 1;
+            }
+        }
+        out.push_str(tag);
+        rest = next;
+    }
+    out
+}
+
+/// The wiki and page path of the wiki-links of `html`.
+fn link_targets(html: &str) -> Vec<(&str, String, String)> {
+    html.match_indices(LINK_PREFIX)
+        .filter_map(|(i, _)| {
+            let href = &html[i + LINK_PREFIX.len()..];
+            let href = &href[..href.find('"')?];
+            let (user, path) = href
+                .strip_prefix("/wikis/")?
+                .split_once('/')
+                .unwrap_or((href.strip_prefix("/wikis/")?, ""));
+            Some((href, user.to_string(), path.to_string()))
+        })
+        .collect()
+}
+
+/// Styles the wiki-links of `html`, served from the wiki of `username`, whose
+/// page does not exist. Links whose page cannot be looked up are left as they
+/// are, and so are links into other wikis that `token` may not read, so that
+/// the styling does not tell which pages a private wiki has.
+pub async fn mark_missing_links(
+    store: &dyn WikiStore,
+    html: &str,
+    username: &str,
+    token: &AuthToken,
+) -> String {
+    let mut checked: HashMap<&str, bool> = HashMap::new();
+    let mut readable: HashMap<String, bool> = HashMap::new();
+    for (href, user, path) in link_targets(html) {
+        if checked.contains_key(href) {
+            continue;
+        }
+        if user != username {
+            let can_read = match readable.get(&user) {
+                Some(&can_read) => can_read,
+                None => {
+                    let can_read = authorize_read(store, &user, token, None).await.is_ok();
+                    readable.insert(user.clone(), can_read);
+                    can_read
+                }
+            };
+            if !can_read {
+                checked.insert(href, true);
+                continue;
+            }
+        }
+        let exists = if path.is_empty() {
+            store.get_wiki(&user).await.map(|w| w.is_some())
+        } else {
+            store.get_page(&user, &path).await.map(|p| p.is_some())
+        };
+        checked.insert(href, exists.unwrap_or(true));
+    }
+    let missing: HashSet<&str> = checked
+        .into_iter()
+        .filter(|(_, exists)| !exists)
+        .map(|(href, _)| href)
+        .collect();
+    let mut html = html.to_string();
+    for href in missing {
+        html = html.replace(
+            &format!("{}{}\"", LINK_PREFIX, href),
+            &format!("{}{}\"", MISSING_LINK_PREFIX, href),
+        );
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::hash_token;
+    use crate::render::render_markdown;
+    use crate::store::{unix_now, MemoryStore, RenderSettings, Visibility};
+
+    #[test]
+    fn test_link_wiki_pages() {
+        assert_eq!(
+            link_wiki_pages("<p>See [[Getting Started]] and [[setup|the setup]].</p>", "alice"),
+            "<p>See <a class=\"wikilink\" href=\"/wikis/alice/getting-started\">Getting Started</a> and <a class=\"wikilink\" href=\"/wikis/alice/setup\">the setup</a>.</p>"
+        );
+        assert_eq!(
+            link_wiki_pages("<p>[[@bob/Guides/Setup]] [[@bob]]</p>", "alice"),
+            "<p><a class=\"wikilink\" href=\"/wikis/bob/guides/setup\">@bob/Guides/Setup</a> <a class=\"wikilink\" href=\"/wikis/bob\">@bob</a></p>"
+        );
+        assert_eq!(
+            link_wiki_pages("<p>[[[Page]]]</p>", "alice"),
+            "<p>[<a class=\"wikilink\" href=\"/wikis/alice/page\">Page</a>]</p>"
+        );
+        // code, other links and invalid targets are left alone
+        for html in [
+            "<pre><code>[[Page]]</code></pre>",
+            "<p><code>[[Page]]</code></p>",
+            "<p><a href=\"https://example.com\">[[Page]]</a></p>",
+            "<p>[[../../etc]] [[Q&amp;A]] [[@]] [[@../x]] [[]] [[Page</p>",
+            "<p><img alt=\"x>[[Page|y onerror=alert(1)]]\"></p>",
+        ] {
+            assert_eq!(link_wiki_pages(html, "alice"), html);
+        }
+    }
+
+    #[test]
+    fn test_rendered_wiki_links() {
+        let settings = RenderSettings::default();
+        let html = render_markdown(
+            "| a |\n| - |\n| [[setup\\|the setup]] |\n\n`[[setup]]` and [[Q&A]]",
+            "alice",
+            &settings,
+        )
+        .unwrap();
+        assert!(html
+            .contains("<td><a class=\"wikilink\" href=\"/wikis/alice/setup\">the setup</a></td>"));
+        assert!(html.contains("<code>[[setup]]</code> and [[Q&amp;A]]"));
+    }
+
+    #[tokio::test]
+    async fn test_mark_missing_links() {
+        let store = MemoryStore::new();
+        for username in ["alice", "bob"] {
+            store
+                .insert_wiki(username, "# hello", "<h1>hello</h1>", "hash")
+                .await
+                .unwrap();
+        }
+        store
+            .insert_page("bob", "notes", "# notes", "<h1>notes</h1>", None, "bob")
+            .await
+            .unwrap();
+        let html = link_wiki_pages(
+            "<p>[[@bob/notes]] [[todo]] [[@bob]] [[@carol]] [[todo|again]]</p>",
+            "alice",
+        );
+        assert_eq!(
+            mark_missing_links(&store, &html, "alice", &AuthToken(None)).await,
+            "<p><a class=\"wikilink\" href=\"/wikis/bob/notes\">@bob/notes</a> <a class=\"wikilink wikilink-missing\" title=\"Create this page\" href=\"/wikis/alice/todo\">todo</a> <a class=\"wikilink\" href=\"/wikis/bob\">@bob</a> <a class=\"wikilink wikilink-missing\" title=\"Create this page\" href=\"/wikis/carol\">@carol</a> <a class=\"wikilink wikilink-missing\" title=\"Create this page\" href=\"/wikis/alice/todo\">again</a></p>"
+        );
+        // pages of wikis the reader may not read all look like they exist
+        store
+            .set_visibility("bob", Visibility::Private)
+            .await
+            .unwrap();
+        store
+            .create_session("bob", &hash_token("bob_session"), unix_now() + 60)
+            .await
+            .unwrap();
+        let html = link_wiki_pages("<p>[[@bob/notes]] [[@bob/secret]]</p>", "alice");
+        assert_eq!(
+            mark_missing_links(&store, &html, "alice", &AuthToken(None)).await,
+            html
+        );
+        assert_eq!(
+            mark_missing_links(
+                &store,
+                &html,
+                "alice",
+                &AuthToken(Some("bob_session".to_string()))
+            )
+            .await,
+            "<p><a class=\"wikilink\" href=\"/wikis/bob/notes\">@bob/notes</a> <a class=\"wikilink wikilink-missing\" title=\"Create this page\" href=\"/wikis/bob/secret\">@bob/secret</a></p>"
+        );
+    }
+}